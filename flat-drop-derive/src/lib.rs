@@ -0,0 +1,480 @@
+//! Derive macros for the `Recursive`, `RecursiveRef` and `RecursiveDebug` traits from the
+//! [`flat-drop`](https://docs.rs/flat-drop) crate.
+//!
+//! You should not normally depend on this crate directly.
+//! Instead, enable the `derive` feature of `flat-drop` and use `flat_drop::Recursive`.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, GenericArgument, Ident,
+    PathArguments, Type,
+};
+
+/// Derives `flat_drop::Recursive` for a struct or enum.
+///
+/// The generated `destruct` moves every `FlatDrop<K>` field out of the value
+/// and lazily yields its container, so that taking a value apart never allocates.
+/// Fields of type `Vec<_>`, `Option<_>` and tuples are
+/// searched (recursively) for `FlatDrop` values too. All other fields are
+/// dropped as normal.
+///
+/// The container type is taken from the first `FlatDrop<K>` field found.
+/// It can be given explicitly with `#[recursive(container = Box<Self>)]`,
+/// which is required if the type has no `FlatDrop` fields, and useful if
+/// the fields' containers convert (via [Into]) into a different container type.
+///
+/// `#[recursive(reentrant)]` sets `Recursive::REENTRANT`, so that nested drops
+/// join the flat drop already in progress. The container type must be `'static`.
+#[proc_macro_derive(Recursive, attributes(recursive))]
+pub fn derive_recursive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_recursive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `flat_drop::RecursiveRef` for a struct or enum that implements `Recursive`.
+///
+/// The generated `children` borrows the container of every `FlatDrop<K>` field, from
+/// the same fields that `#[derive(Recursive)]` moves them out of, so each `K` must be
+/// the type's container itself.
+#[proc_macro_derive(RecursiveRef, attributes(recursive))]
+pub fn derive_recursive_ref(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_recursive_ref(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `flat_drop::RecursiveDebug` for a struct or enum that implements `RecursiveRef`.
+///
+/// Each node is named after the struct, or the variant of the enum. The fields that
+/// don't contain a `FlatDrop` are written first, and must implement `Debug`,
+/// followed by the children. Together with `RecursiveRef`, this implements `Debug` for
+/// `FlatDrop`s of the type, so types holding them can derive `Debug` as before.
+#[proc_macro_derive(RecursiveDebug, attributes(recursive))]
+pub fn derive_recursive_debug(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_recursive_debug(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_recursive(input: DeriveInput) -> syn::Result<TokenStream> {
+    let Attributes {
+        mut container,
+        reentrant,
+    } = attributes(&input)?;
+
+    let mut fresh = Fresh::default();
+    let variants = variants(&input, "Recursive")?;
+    let (fill, slots) = fill_slots(&variants, Mode::Move, contains_flat_drop, &mut fresh);
+    // The containers are yielded lazily, so taking a node apart never allocates.
+    let mut children = Vec::new();
+    for slot in &slots {
+        children.push(containers(
+            slot.ident.to_token_stream(),
+            &Kind::Iterable(&slot.ty),
+            Mode::Move,
+            &mut container,
+            &mut fresh,
+        ));
+    }
+    let children = chain(children);
+
+    let Some(container) = container else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "could not find a `FlatDrop` field to infer the container type from; \
+             add `#[recursive(container = ...)]`",
+        ));
+    };
+
+    let reentrant = reentrant.then(|| {
+        quote! {
+            const REENTRANT: ::core::option::Option<::flat_drop::Reentrant<Self::Container>> =
+                ::core::option::Option::Some(::flat_drop::Reentrant::new());
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::flat_drop::Recursive for #name #ty_generics #where_clause {
+            type Container = #container;
+
+            #reentrant
+
+            #[allow(unreachable_code)]
+            fn destruct(self) -> impl ::core::iter::Iterator<Item = Self::Container> {
+                #fill
+                #children
+            }
+        }
+    })
+}
+
+fn expand_recursive_ref(input: DeriveInput) -> syn::Result<TokenStream> {
+    let mut fresh = Fresh::default();
+    let variants = variants(&input, "RecursiveRef")?;
+    let (fill, slots) = fill_slots(&variants, Mode::Borrow, contains_flat_drop, &mut fresh);
+    let mut children = Vec::new();
+    for slot in &slots {
+        children.push(containers(
+            slot.ident.to_token_stream(),
+            &Kind::Iterable(&slot.ty),
+            Mode::Borrow,
+            &mut None,
+            &mut fresh,
+        ));
+    }
+    let children = chain(children);
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::flat_drop::RecursiveRef for #name #ty_generics #where_clause {
+            #[allow(unreachable_code)]
+            fn children(&self) -> impl ::core::iter::Iterator<Item = &Self::Container> {
+                #fill
+                #children
+            }
+        }
+    })
+}
+
+fn expand_recursive_debug(input: DeriveInput) -> syn::Result<TokenStream> {
+    let mut fresh = Fresh::default();
+    let variants = variants(&input, "RecursiveDebug")?;
+    let names = variants.iter().map(|variant| {
+        let path = &variant.path;
+        let name = variant.name.to_string();
+        quote!(#path { .. } => #name)
+    });
+    let not_flat_drop = |ty: &Type| !contains_flat_drop(ty);
+    let (fill, slots) = fill_slots(&variants, Mode::Borrow, not_flat_drop, &mut fresh);
+    let fields = chain(
+        slots
+            .iter()
+            .map(|slot| {
+                let ident = &slot.ident;
+                let field = fresh.next();
+                quote! {
+                    ::core::iter::IntoIterator::into_iter(#ident)
+                        .map(|#field| #field as &dyn ::core::fmt::Debug)
+                }
+            })
+            .collect(),
+    );
+
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for slot in &slots {
+        let ty = &slot.ty;
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#ty: ::core::fmt::Debug));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::flat_drop::RecursiveDebug for #name #ty_generics #where_clause {
+            fn debug_name(&self) -> &str {
+                match *self {
+                    #(#names,)*
+                }
+            }
+
+            #[allow(unreachable_code)]
+            fn debug_fields(&self) -> impl ::core::iter::Iterator<Item = &dyn ::core::fmt::Debug> {
+                #fill
+                #fields
+            }
+        }
+    })
+}
+
+/// The options given in `#[recursive(...)]` attributes on the type.
+#[derive(Default)]
+struct Attributes {
+    container: Option<Type>,
+    reentrant: bool,
+}
+
+/// Parses `#[recursive(container = ..., reentrant)]`, if present.
+fn attributes(input: &DeriveInput) -> syn::Result<Attributes> {
+    let mut attributes = Attributes::default();
+    for attr in &input.attrs {
+        if !attr.path().is_ident("recursive") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("container") {
+                attributes.container = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("reentrant") {
+                attributes.reentrant = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown `recursive` attribute"))
+            }
+        })?;
+    }
+    Ok(attributes)
+}
+
+/// A struct, or one variant of an enum.
+struct Variant<'a> {
+    /// `Self` for a struct, or `Self::Name` for a variant.
+    path: TokenStream,
+    name: &'a Ident,
+    fields: &'a Fields,
+}
+
+/// Lists the variants of an enum, or the struct itself.
+fn variants<'a>(input: &'a DeriveInput, derive: &str) -> syn::Result<Vec<Variant<'a>>> {
+    match &input.data {
+        Data::Struct(data) => Ok(Vec::from([Variant {
+            path: quote!(Self),
+            name: &input.ident,
+            fields: &data.fields,
+        }])),
+        Data::Enum(data) => Ok(data
+            .variants
+            .iter()
+            .map(|variant| {
+                let name = &variant.ident;
+                Variant {
+                    path: quote!(Self::#name),
+                    name,
+                    fields: &variant.fields,
+                }
+            })
+            .collect()),
+        Data::Union(data) => Err(syn::Error::new(
+            data.union_token.span(),
+            format!("`{derive}` cannot be derived for unions"),
+        )),
+    }
+}
+
+/// Whether fields are moved out of `self` or borrowed from it.
+#[derive(Clone, Copy)]
+enum Mode {
+    Move,
+    Borrow,
+}
+
+/// A variable holding one of the selected fields, if the value matched has that field.
+struct Slot {
+    ident: Ident,
+    /// The type of the field.
+    ty: Type,
+}
+
+/// Generates a statement that matches `self` and puts every field whose type is
+/// selected into its own slot, leaving the other variants' slots empty.
+fn fill_slots(
+    variants: &[Variant],
+    mode: Mode,
+    select: impl Fn(&Type) -> bool,
+    fresh: &mut Fresh,
+) -> (TokenStream, Vec<Slot>) {
+    let mut slots = Vec::new();
+    // The pattern for each variant, and the slots it fills with which bindings.
+    let mut filled = Vec::new();
+    for variant in variants {
+        let mut patterns = Vec::new();
+        let mut bindings = Vec::new();
+        for (index, field) in variant.fields.iter().enumerate() {
+            if !select(&field.ty) {
+                continue;
+            }
+            let member = match &field.ident {
+                Some(ident) => quote!(#ident),
+                None => {
+                    let index = syn::Index::from(index);
+                    quote!(#index)
+                }
+            };
+            let binding = fresh.next();
+            patterns.push(quote!(#member: #binding));
+            bindings.push((slots.len(), binding));
+            slots.push(Slot {
+                ident: fresh.next(),
+                ty: field.ty.clone(),
+            });
+        }
+        let path = &variant.path;
+        filled.push((quote!(#path { #(#patterns,)* .. }), bindings));
+    }
+    if slots.is_empty() {
+        return (TokenStream::new(), slots);
+    }
+
+    let arms = filled.iter().map(|(pattern, bindings)| {
+        let values = slots.iter().enumerate().map(|(index, slot)| {
+            match bindings.iter().find(|(filled, _)| *filled == index) {
+                Some((_, binding)) => quote!(::core::option::Option::Some(#binding)),
+                None => {
+                    let ty = &slot.ty;
+                    match mode {
+                        Mode::Move => quote!(::core::option::Option::<#ty>::None),
+                        Mode::Borrow => quote!(::core::option::Option::<&#ty>::None),
+                    }
+                }
+            }
+        });
+        quote!(#pattern => (#(#values,)*))
+    });
+    let idents = slots.iter().map(|slot| &slot.ident);
+    let fill = quote! {
+        let (#(#idents,)*) = match self {
+            #(#arms,)*
+        };
+    };
+    (fill, slots)
+}
+
+/// Generates an iterator yielding everything that each of `iterators` does, in turn.
+fn chain(iterators: Vec<TokenStream>) -> TokenStream {
+    match iterators.split_first() {
+        Some((first, rest)) => quote!(#first #(.chain(#rest))*),
+        None => quote!(::core::iter::empty()),
+    }
+}
+
+/// Generates an iterator that moves every `FlatDrop` out of `value`
+/// (of the given kind) and yields its container, or borrows each container.
+fn containers(
+    value: TokenStream,
+    kind: &Kind,
+    mode: Mode,
+    container: &mut Option<Type>,
+    fresh: &mut Fresh,
+) -> TokenStream {
+    match kind {
+        Kind::FlatDrop(inner) => {
+            container.get_or_insert_with(|| (*inner).clone());
+            let child = into_container(value, mode);
+            quote!(::core::iter::once(#child))
+        }
+        Kind::Iterable(element) => {
+            let item = fresh.next();
+            match classify(element) {
+                // Mapping keeps the size hint exact, unlike flattening.
+                Some(Kind::FlatDrop(inner)) => {
+                    container.get_or_insert_with(|| inner.clone());
+                    let child = into_container(item.to_token_stream(), mode);
+                    quote! {
+                        ::core::iter::IntoIterator::into_iter(#value).map(|#item| #child)
+                    }
+                }
+                Some(kind) => {
+                    let body = containers(item.to_token_stream(), &kind, mode, container, fresh);
+                    quote! {
+                        ::core::iter::IntoIterator::into_iter(#value).flat_map(|#item| #body)
+                    }
+                }
+                None => unreachable!("only fields containing a `FlatDrop` have slots"),
+            }
+        }
+        Kind::Tuple(elements) => {
+            let mut patterns = Vec::new();
+            let mut children = Vec::new();
+            for element in elements {
+                match classify(element).filter(|_| contains_flat_drop(element)) {
+                    Some(kind) => {
+                        let binding = fresh.next();
+                        children.push(containers(
+                            binding.to_token_stream(),
+                            &kind,
+                            mode,
+                            container,
+                            fresh,
+                        ));
+                        patterns.push(quote!(#binding));
+                    }
+                    None => patterns.push(quote!(_)),
+                }
+            }
+            let children = chain(children);
+            quote! {{
+                let (#(#patterns,)*) = #value;
+                #children
+            }}
+        }
+    }
+}
+
+/// Generates an expression converting the `FlatDrop` `value` into `Self::Container`,
+/// or borrowing its container.
+fn into_container(value: TokenStream, mode: Mode) -> TokenStream {
+    match mode {
+        Mode::Move => quote! {
+            ::core::convert::Into::<Self::Container>::into(::flat_drop::FlatDrop::into_inner(#value))
+        },
+        Mode::Borrow => quote!(::core::ops::Deref::deref(#value)),
+    }
+}
+
+/// The shapes of field type that we know how to search for `FlatDrop`s.
+enum Kind<'a> {
+    /// `FlatDrop<K>`, storing `K`.
+    FlatDrop(&'a Type),
+    /// `Vec<T>` or `Option<T>`, storing `T`.
+    Iterable(&'a Type),
+    /// `(T, U, ...)`.
+    Tuple(Vec<&'a Type>),
+}
+
+fn classify(ty: &Type) -> Option<Kind<'_>> {
+    match ty {
+        Type::Paren(paren) => classify(&paren.elem),
+        Type::Group(group) => classify(&group.elem),
+        Type::Tuple(tuple) => Some(Kind::Tuple(tuple.elems.iter().collect())),
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = path.path.segments.last()?;
+            let PathArguments::AngleBracketed(args) = &segment.arguments else {
+                return None;
+            };
+            let mut types = args.args.iter().filter_map(|arg| match arg {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            });
+            let arg = types.next()?;
+            if types.next().is_some() {
+                return None;
+            }
+            if segment.ident == "FlatDrop" {
+                Some(Kind::FlatDrop(arg))
+            } else if segment.ident == "Vec" || segment.ident == "Option" {
+                Some(Kind::Iterable(arg))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn contains_flat_drop(ty: &Type) -> bool {
+    match classify(ty) {
+        Some(Kind::FlatDrop(_)) => true,
+        Some(Kind::Iterable(element)) => contains_flat_drop(element),
+        Some(Kind::Tuple(elements)) => elements.into_iter().any(contains_flat_drop),
+        None => false,
+    }
+}
+
+/// Generates unique identifiers for bindings in the expanded code.
+#[derive(Default)]
+struct Fresh(usize);
+
+impl Fresh {
+    fn next(&mut self) -> Ident {
+        self.0 += 1;
+        format_ident!("__field{}", self.0, span = Span::call_site())
+    }
+}
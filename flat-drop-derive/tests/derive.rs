@@ -0,0 +1,145 @@
+use std::{cell::Cell, rc::Rc};
+
+use flat_drop::{FlatDrop, Recursive, RecursiveDebug, RecursiveRef};
+
+/// Peano natural numbers.
+#[derive(Recursive)]
+enum Natural {
+    Zero,
+    Succ(FlatDrop<Box<Natural>>),
+}
+
+impl Natural {
+    pub fn from_usize(value: usize) -> Self {
+        (0..value).fold(Self::Zero, |nat, _| {
+            Self::Succ(FlatDrop::new(Box::new(nat)))
+        })
+    }
+}
+
+#[test]
+fn test_large_natural() {
+    // Create a new thread with a 4kb stack and allocate a number far bigger than 4 * 1024.
+    const STACK_SIZE: usize = 4 * 1024;
+
+    fn task() {
+        let nat = Natural::from_usize(STACK_SIZE * 100);
+        drop(std::hint::black_box(nat));
+    }
+
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(task)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// Increments a counter when dropped.
+struct Counted(Rc<Cell<usize>>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+/// A node with children in every position the derive macro knows about.
+#[derive(Recursive)]
+struct Node {
+    #[allow(dead_code)]
+    counted: Counted,
+    first: Option<FlatDrop<Box<Node>>>,
+    rest: Vec<FlatDrop<Box<Node>>>,
+    pair: (u8, Option<FlatDrop<Box<Node>>>),
+}
+
+impl Node {
+    fn leaf(counter: &Rc<Cell<usize>>) -> Self {
+        Self {
+            counted: Counted(counter.clone()),
+            first: None,
+            rest: Vec::new(),
+            pair: (0, None),
+        }
+    }
+}
+
+#[test]
+fn test_destruct_fields() {
+    let counter = Rc::new(Cell::new(0));
+    let node = Node {
+        first: Some(FlatDrop::new_boxed(Node::leaf(&counter))),
+        rest: vec![
+            FlatDrop::new_boxed(Node::leaf(&counter)),
+            FlatDrop::new_boxed(Node::leaf(&counter)),
+        ],
+        pair: (1, Some(FlatDrop::new_boxed(Node::leaf(&counter)))),
+        ..Node::leaf(&counter)
+    };
+
+    let children = node.destruct().collect::<Vec<_>>();
+    assert_eq!(children.len(), 4);
+    // Only the root has been dropped; its children were moved out.
+    assert_eq!(counter.get(), 1);
+    drop(children);
+    assert_eq!(counter.get(), 5);
+}
+
+/// A tuple struct with an explicit container type and no `FlatDrop` fields.
+#[derive(Recursive)]
+#[recursive(container = Box<Self>)]
+struct Leaf(#[allow(dead_code)] u32);
+
+#[test]
+fn test_explicit_container() {
+    assert_eq!(Leaf(3).destruct().count(), 0);
+    assert!(Leaf::REENTRANT.is_none());
+}
+
+#[derive(Recursive)]
+#[recursive(reentrant)]
+enum List {
+    Nil,
+    Cons(FlatDrop<Box<List>>),
+}
+
+#[test]
+fn test_reentrant() {
+    assert!(List::REENTRANT.is_some());
+    drop(FlatDrop::new_boxed(List::Cons(FlatDrop::new_boxed(
+        List::Nil,
+    ))));
+}
+
+/// An expression whose nodes have fields as well as children.
+#[derive(Recursive, RecursiveRef, RecursiveDebug, Debug)]
+enum Expr {
+    Num(i64),
+    Neg(FlatDrop<Box<Expr>>),
+    Call(
+        String,
+        Vec<FlatDrop<Box<Expr>>>,
+        Option<FlatDrop<Box<Expr>>>,
+    ),
+}
+
+#[test]
+fn test_debug() {
+    let num = |n| FlatDrop::new_boxed(Expr::Num(n));
+    let call = Expr::Call(
+        "f".to_owned(),
+        vec![num(1), FlatDrop::new_boxed(Expr::Neg(num(2)))],
+        Some(num(3)),
+    );
+    assert_eq!(call.children().count(), 3);
+    assert_eq!(call.debug_name(), "Call");
+    assert_eq!(
+        format!("{:?}", FlatDrop::new_boxed(call)),
+        r#"Call("f", Num(1), Neg(Num(2)), Num(3))"#
+    );
+
+    // The derived `Debug` of a type holding a `FlatDrop` uses its iterative `Debug`.
+    let neg = Expr::Neg(num(4));
+    assert_eq!(format!("{neg:?}"), "Neg(Num(4))");
+}
@@ -0,0 +1,108 @@
+//! Compares the current dropping procedure against the original one,
+//! which allocated a fresh worklist every time any `FlatDrop` was dropped.
+//!
+//! - `leaves` drops many trees without children, where the original allocated
+//!   a worklist for each one and the current one never touches its worklist.
+//! - `chain` drops one long tree, where each node has a leaf as its second child.
+//!   Both procedures allocate a worklist once and push two children per node,
+//!   so this measures the main loop itself: `current` should be no slower.
+//! - `balanced` drops one wide tree, where the current worklist starts out
+//!   with the spare allocation from the last drop instead of growing from empty.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use flat_drop::{FlatDrop, IntoOptionInner, Recursive};
+
+/// A binary tree.
+enum Tree {
+    Leaf,
+    Node(FlatDrop<Box<Tree>>, FlatDrop<Box<Tree>>),
+}
+
+impl Recursive for Tree {
+    type Container = Box<Tree>;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        match self {
+            Tree::Leaf => None,
+            Tree::Node(left, right) => Some([left.into_inner(), right.into_inner()]),
+        }
+        .into_iter()
+        .flatten()
+    }
+}
+
+impl Tree {
+    fn chain(length: usize) -> FlatDrop<Box<Tree>> {
+        (0..length).fold(FlatDrop::new_boxed(Tree::Leaf), |tree, _| {
+            FlatDrop::new_boxed(Tree::Node(tree, FlatDrop::new_boxed(Tree::Leaf)))
+        })
+    }
+
+    fn balanced(depth: usize) -> FlatDrop<Box<Tree>> {
+        if depth == 0 {
+            FlatDrop::new_boxed(Tree::Leaf)
+        } else {
+            FlatDrop::new_boxed(Tree::Node(
+                Self::balanced(depth - 1),
+                Self::balanced(depth - 1),
+            ))
+        }
+    }
+}
+
+/// The original implementation of `Drop for FlatDrop`.
+fn drop_original<K>(value: FlatDrop<K>)
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let mut to_drop = vec![value.into_inner()];
+    while let Some(container) = to_drop.pop() {
+        if let Some(value) = container.into_option_inner() {
+            to_drop.extend(value.destruct());
+        }
+    }
+}
+
+fn bench_drop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("leaves");
+    let leaves = || {
+        (0..100_000)
+            .map(|_| FlatDrop::new_boxed(Tree::Leaf))
+            .collect::<Vec<_>>()
+    };
+    group.bench_function("current", |b| {
+        b.iter_batched(
+            leaves,
+            |leaves| drop(black_box(leaves)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("original", |b| {
+        b.iter_batched(
+            leaves,
+            |leaves| leaves.into_iter().for_each(drop_original),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    for (name, build) in [
+        ("chain", (|| Tree::chain(100_000)) as fn() -> _),
+        ("balanced", || Tree::balanced(16)),
+    ] {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("current", |b| {
+            b.iter_batched(build, |tree| drop(black_box(tree)), BatchSize::LargeInput)
+        });
+        group.bench_function("original", |b| {
+            b.iter_batched(build, drop_original, BatchSize::LargeInput)
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_drop);
+criterion_main!(benches);
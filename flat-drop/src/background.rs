@@ -0,0 +1,95 @@
+//! Dropping recursive objects on a dedicated background thread.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        OnceLock,
+    },
+    thread,
+};
+
+use crate::{FlatContainer, FlatDrop};
+
+/// An object waiting to be dropped by the reclaimer thread.
+type Garbage = Box<dyn Send>;
+
+/// The sending half of the channel to the reclaimer thread.
+/// This is `None` if the thread could not be spawned.
+static RECLAIMER: OnceLock<Option<Sender<Garbage>>> = OnceLock::new();
+
+/// Returns the channel to the reclaimer thread, spawning it if it is not yet running.
+fn reclaimer() -> Option<&'static Sender<Garbage>> {
+    RECLAIMER
+        .get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<Garbage>();
+            thread::Builder::new()
+                .name("flat-drop-reclaimer".to_owned())
+                .spawn(move || {
+                    for garbage in receiver {
+                        // A panic while dropping one object should not stop us from
+                        // dropping all of the others.
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(garbage)));
+                    }
+                })
+                .ok()
+                .map(|_| sender)
+        })
+        .as_ref()
+}
+
+impl<K> FlatDrop<K>
+where
+    K: FlatContainer + Send + 'static,
+{
+    /// Drops this object on a dedicated reclaimer thread instead of the current thread.
+    /// The reclaimer thread is spawned the first time this function is called,
+    /// and runs the usual iterative dropping procedure on each object it is sent.
+    ///
+    /// This is useful when destroying a large object would otherwise block the
+    /// current thread for too long.
+    /// If the reclaimer thread is unavailable, the object is dropped on the current thread.
+    pub fn drop_in_background(self) {
+        if let Some(sender) = reclaimer() {
+            // If the reclaimer has gone away, the object is handed back
+            // inside the error and dropped here instead.
+            let _ = sender.send(Box::new(self));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread, time::Duration};
+
+    use crate::{tests::ReportThread, FlatDrop, Recursive};
+
+    enum List {
+        Nil(#[allow(dead_code)] ReportThread),
+        Cons(FlatDrop<Box<List>>),
+    }
+
+    impl Recursive for List {
+        type Container = Box<List>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                List::Nil(_) => None,
+                List::Cons(tail) => Some(tail.into_inner()),
+            }
+            .into_iter()
+        }
+    }
+
+    #[test]
+    fn test_drop_in_background() {
+        let (sender, receiver) = mpsc::channel();
+        let list = (0..100_000).fold(List::Nil(ReportThread(sender)), |list, _| {
+            List::Cons(FlatDrop::new_boxed(list))
+        });
+
+        FlatDrop::new_boxed(list).drop_in_background();
+        let dropped_on = receiver.recv_timeout(Duration::from_secs(60)).unwrap();
+        assert_ne!(dropped_on, thread::current().id());
+    }
+}
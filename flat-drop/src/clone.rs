@@ -0,0 +1,185 @@
+//! Cloning recursive objects iteratively.
+
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use core::ops::Deref;
+
+use crate::{FlatDrop, IntoOptionInner, RecursiveRef};
+
+/// A container that can be cloned one level at a time.
+///
+/// Cloning a [Box] requires cloning its contents, so we do that ourselves, one node at a
+/// time. Shared pointers like [Rc] and [Arc] are cloned by incrementing their reference
+/// counts, which doesn't need to look at the contents at all.
+pub trait CloneContainer: IntoOptionInner + Deref<Target = Self::Inner> + Sized {
+    /// Clones the container if this can be done without cloning its contents.
+    fn try_share(&self) -> Option<Self>;
+
+    /// Creates a new container holding a clone of the contents.
+    fn from_inner(inner: Self::Inner) -> Self;
+}
+
+/// A recursive type that can be cloned one node at a time.
+pub trait RecursiveClone: RecursiveRef {
+    /// Clones this node, but not its children.
+    /// `children` yields clones of the containers from [RecursiveRef::children], in order,
+    /// which should be used in place of the originals.
+    fn clone_with_children(&self, children: impl Iterator<Item = Self::Container>) -> Self;
+}
+
+impl<T> CloneContainer for Box<T> {
+    fn try_share(&self) -> Option<Self> {
+        None
+    }
+
+    fn from_inner(inner: T) -> Self {
+        Box::new(inner)
+    }
+}
+
+impl<T> CloneContainer for Rc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(Rc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        Rc::new(inner)
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<T> CloneContainer for Arc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(Arc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        Arc::new(inner)
+    }
+}
+
+/// A step of the iterative cloning procedure.
+enum Frame<'a, K: IntoOptionInner> {
+    /// Clone this container.
+    Enter(&'a K),
+    /// The given number of children of this node have been cloned,
+    /// so clone the node itself.
+    Exit(&'a K::Inner, usize),
+}
+
+/// Clones `root` and everything inside it, without recursion.
+fn clone_iterative<K>(root: &K) -> K
+where
+    K: CloneContainer,
+    K::Inner: RecursiveClone<Container = K>,
+{
+    // Clones are built in post-order: each node's children are on top of `cloned`
+    // by the time we reach its `Exit` frame.
+    let mut cloned = Vec::new();
+    let mut frames = vec![Frame::Enter(root)];
+    while let Some(frame) = frames.pop() {
+        match frame {
+            Frame::Enter(container) => {
+                if let Some(clone) = container.try_share() {
+                    cloned.push(clone);
+                    continue;
+                }
+                let node = &**container;
+                let start = frames.len() + 1;
+                frames.push(Frame::Exit(node, 0));
+                frames.extend(node.children().map(Frame::Enter));
+                let count = frames.len() - start;
+                frames[start - 1] = Frame::Exit(node, count);
+                // Visit the first child first, so that its clone is pushed first.
+                frames[start..].reverse();
+            }
+            Frame::Exit(node, count) => {
+                let children = cloned.drain(cloned.len() - count..);
+                let clone = K::from_inner(node.clone_with_children(children));
+                cloned.push(clone);
+            }
+        }
+    }
+    cloned.pop().expect("the root was cloned")
+}
+
+impl<K> Clone for FlatDrop<K>
+where
+    K: CloneContainer,
+    K::Inner: RecursiveClone<Container = K>,
+{
+    fn clone(&self) -> Self {
+        Self::new(clone_iterative(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        FlatDrop, Recursive, RecursiveClone, RecursiveRef,
+    };
+
+    #[test]
+    fn test_clone_large_natural() {
+        on_small_stack(|| {
+            let nat = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let clone = std::hint::black_box(nat.clone());
+            assert_eq!(clone.to_usize(), STACK_SIZE * 100);
+        });
+    }
+
+    /// A binary tree sharing its subtrees.
+    enum Tree {
+        Leaf(u32),
+        Node(FlatDrop<Rc<Tree>>, FlatDrop<Rc<Tree>>),
+    }
+
+    impl Recursive for Tree {
+        type Container = Rc<Tree>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Tree::Leaf(_) => None,
+                Tree::Node(left, right) => Some([left.into_inner(), right.into_inner()]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursiveRef for Tree {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            match self {
+                Tree::Leaf(_) => None,
+                Tree::Node(left, right) => Some([&**left, &**right]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursiveClone for Tree {
+        fn clone_with_children(&self, mut children: impl Iterator<Item = Rc<Tree>>) -> Self {
+            match self {
+                Tree::Leaf(value) => Tree::Leaf(*value),
+                Tree::Node(..) => Tree::Node(
+                    FlatDrop::new(children.next().unwrap()),
+                    FlatDrop::new(children.next().unwrap()),
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_clone_shares() {
+        let left = FlatDrop::new_rc(Tree::Leaf(1));
+        let tree = FlatDrop::new_rc(Tree::Node(left.clone(), FlatDrop::new_rc(Tree::Leaf(2))));
+        let clone = tree.clone();
+        assert!(Rc::ptr_eq(&tree, &clone));
+        assert_eq!(Rc::strong_count(&left), 2);
+    }
+}
@@ -0,0 +1,247 @@
+//! Comparing recursive objects iteratively.
+
+use alloc::vec;
+use core::{cmp::Ordering, ops::Deref};
+
+use crate::{FlatDrop, IntoOptionInner, RecursiveRef};
+
+/// A recursive type whose values can be compared for equality one node at a time.
+///
+/// Two values are equal if [RecursivePartialEq::shallow_eq] holds for their roots,
+/// they have the same number of children, and each pair of corresponding children
+/// is equal.
+pub trait RecursivePartialEq: RecursiveRef {
+    /// Whether every node is equal to itself, as for types that implement [RecursiveEq].
+    /// If so, children in containers that share their value (see
+    /// [IntoOptionInner::ptr_eq]) are equal without being compared, which makes
+    /// comparing shared structures with themselves fast. `false` by default.
+    const REFLEXIVE: bool = false;
+
+    /// Compares the parts of two nodes that aren't children.
+    fn shallow_eq(&self, other: &Self) -> bool;
+}
+
+/// Marks that [RecursivePartialEq::shallow_eq] is an equivalence relation,
+/// in the same way that [Eq] does for [PartialEq].
+/// Implementations should also set [RecursivePartialEq::REFLEXIVE].
+pub trait RecursiveEq: RecursivePartialEq {}
+
+/// A recursive type whose values can be ordered one node at a time.
+///
+/// Values are compared lexicographically in pre-order: first by
+/// [RecursivePartialOrd::shallow_partial_cmp] on their roots, then by comparing their
+/// children in turn. If one value's children are a prefix of the other's, it is smaller.
+pub trait RecursivePartialOrd: RecursivePartialEq {
+    /// Compares the parts of two nodes that aren't children.
+    /// This should return `Some(Ordering::Equal)` exactly when
+    /// [RecursivePartialEq::shallow_eq] holds.
+    fn shallow_partial_cmp(&self, other: &Self) -> Option<Ordering>;
+}
+
+/// A recursive type whose values are totally ordered, in the same way that [Ord]
+/// refines [PartialOrd].
+pub trait RecursiveOrd: RecursiveEq + RecursivePartialOrd {
+    /// Compares the parts of two nodes that aren't children.
+    /// This must agree with [RecursivePartialOrd::shallow_partial_cmp].
+    fn shallow_cmp(&self, other: &Self) -> Ordering;
+}
+
+/// Compares `a` and `b` for equality, without recursion.
+fn eq_iterative<K>(a: &K, b: &K) -> bool
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursivePartialEq<Container = K>,
+{
+    let mut pairs = vec![(a, b)];
+    while let Some((a, b)) = pairs.pop() {
+        if K::Inner::REFLEXIVE && a.ptr_eq(b) {
+            continue;
+        }
+        if !a.shallow_eq(b) {
+            return false;
+        }
+        let mut a_children = a.children();
+        let mut b_children = b.children();
+        loop {
+            match (a_children.next(), b_children.next()) {
+                (Some(a), Some(b)) => pairs.push((a, b)),
+                (None, None) => break,
+                _ => return false,
+            }
+        }
+    }
+    true
+}
+
+/// Compares `a` and `b` lexicographically using `shallow_cmp`, without recursion.
+fn cmp_iterative<K>(
+    a: &K,
+    b: &K,
+    shallow_cmp: impl Fn(&K::Inner, &K::Inner) -> Option<Ordering>,
+) -> Option<Ordering>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveRef<Container = K>,
+{
+    match shallow_cmp(a, b) {
+        Some(Ordering::Equal) => {}
+        ordering => return ordering,
+    }
+
+    // The children of each pair of nodes that we're partway through comparing.
+    let mut stack = vec![(a.children(), b.children())];
+    while let Some((a_children, b_children)) = stack.last_mut() {
+        match (a_children.next(), b_children.next()) {
+            (Some(a), Some(b)) => {
+                match shallow_cmp(a, b) {
+                    Some(Ordering::Equal) => {}
+                    ordering => return ordering,
+                }
+                stack.push((a.children(), b.children()));
+            }
+            (None, None) => {
+                stack.pop();
+            }
+            (None, Some(_)) => return Some(Ordering::Less),
+            (Some(_), None) => return Some(Ordering::Greater),
+        }
+    }
+    Some(Ordering::Equal)
+}
+
+impl<K> PartialEq for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursivePartialEq<Container = K>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        eq_iterative(&**self, &**other)
+    }
+}
+
+impl<K> Eq for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveEq<Container = K>,
+{
+}
+
+impl<K> PartialOrd for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursivePartialOrd<Container = K>,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        cmp_iterative(&**self, &**other, RecursivePartialOrd::shallow_partial_cmp)
+    }
+}
+
+impl<K> Ord for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveOrd<Container = K>,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_iterative(&**self, &**other, |a, b| Some(a.shallow_cmp(b)))
+            .expect("total orders always compare")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{collections::BTreeMap, rc::Rc};
+
+    use super::{RecursiveEq, RecursivePartialEq};
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        FlatDrop, Recursive, RecursiveRef,
+    };
+
+    /// A binary tree whose nodes may be shared, so that it can be exponentially
+    /// larger than the number of distinct nodes.
+    enum Dag {
+        Leaf,
+        Pair(FlatDrop<Rc<Dag>>, FlatDrop<Rc<Dag>>),
+    }
+
+    impl Recursive for Dag {
+        type Container = Rc<Dag>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Dag::Leaf => None,
+                Dag::Pair(a, b) => Some([a.into_inner(), b.into_inner()]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursiveRef for Dag {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            match self {
+                Dag::Leaf => None,
+                Dag::Pair(a, b) => Some([&**a, &**b]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursivePartialEq for Dag {
+        const REFLEXIVE: bool = true;
+
+        fn shallow_eq(&self, other: &Self) -> bool {
+            matches!(
+                (self, other),
+                (Dag::Leaf, Dag::Leaf) | (Dag::Pair(..), Dag::Pair(..))
+            )
+        }
+    }
+
+    impl RecursiveEq for Dag {}
+
+    #[test]
+    fn test_eq_large_natural() {
+        on_small_stack(|| {
+            let a = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let b = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let c = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100 + 1));
+            assert!(a == b);
+            assert!(a != c);
+        });
+    }
+
+    #[test]
+    fn test_eq_shared() {
+        // Each node's children are the same node, so this has 2^64 paths through it,
+        // and can only be compared with itself by skipping shared children.
+        let dag = (0..64).fold(Rc::new(Dag::Leaf), |dag, _| {
+            Rc::new(Dag::Pair(
+                FlatDrop::new(Rc::clone(&dag)),
+                FlatDrop::new(dag),
+            ))
+        });
+        assert!(FlatDrop::new(Rc::clone(&dag)) == FlatDrop::new(Rc::clone(&dag)));
+
+        // Distinct nodes are still compared.
+        let leaf = || FlatDrop::new(Rc::new(Dag::Leaf));
+        let pair = || FlatDrop::new(Rc::new(Dag::Pair(leaf(), leaf())));
+        assert!(pair() == pair());
+        assert!(pair() != leaf());
+    }
+
+    #[test]
+    fn test_ord_large_natural() {
+        on_small_stack(|| {
+            let sizes = [3, 1, 4, 1, 5].map(|n| n * STACK_SIZE * 20);
+            let mut nats = sizes.map(|n| FlatDrop::new_boxed(Natural::from_usize(n)));
+            nats.sort();
+            let sorted = nats.each_ref().map(|nat| nat.to_usize());
+            assert_eq!(sorted, [1, 1, 3, 4, 5].map(|n| n * STACK_SIZE * 20));
+
+            let map = BTreeMap::from(nats.map(|nat| (nat, ())));
+            assert_eq!(map.len(), 4);
+        });
+    }
+}
@@ -0,0 +1,359 @@
+//! Formatting recursive objects iteratively.
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Debug, Formatter, Write},
+    iter::Peekable,
+    ops::Deref,
+};
+
+use crate::{FlatDrop, IntoOptionInner, RecursiveRef};
+
+/// A recursive type whose values can be formatted one node at a time.
+///
+/// Each node is written like a tuple struct, `Name(field, .., child, ..)`,
+/// or just `Name` if it has no fields or children.
+pub trait RecursiveDebug: RecursiveRef {
+    /// The name of this node, such as the name of its type or variant.
+    fn debug_name(&self) -> &str;
+
+    /// The parts of this node that aren't children, which are written before its children.
+    fn debug_fields(&self) -> impl Iterator<Item = &dyn Debug> {
+        core::iter::empty()
+    }
+}
+
+/// Formats a [FlatDrop] like its [Debug] implementation, but elides deeply nested
+/// nodes and long lists of children as `..`.
+/// See [FlatDrop::debug_truncated].
+pub struct DebugTruncated<'a, K> {
+    value: &'a K,
+    max_depth: usize,
+    max_children: usize,
+}
+
+impl<K> FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveDebug<Container = K>,
+{
+    /// Returns a wrapper that formats this object with [Debug], except that nodes nested
+    /// more than `max_depth` levels below the root, and all but the first `max_children`
+    /// children of each node, are written as `..`.
+    pub fn debug_truncated(&self, max_depth: usize, max_children: usize) -> DebugTruncated<'_, K> {
+        DebugTruncated {
+            value: self,
+            max_depth,
+            max_children,
+        }
+    }
+}
+
+impl<K> Debug for DebugTruncated<'_, K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveDebug<Container = K>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::new(f, self.max_depth, self.max_children).print(self.value)
+    }
+}
+
+impl<K> Debug for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveDebug<Container = K>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::new(f, usize::MAX, usize::MAX).print(&**self)
+    }
+}
+
+/// A node whose opening parenthesis has been written, but not its closing one.
+struct Open<I: Iterator> {
+    children: Peekable<I>,
+    depth: usize,
+    /// How many fields and children have been written so far.
+    items: usize,
+    /// How many children have been written so far.
+    written_children: usize,
+}
+
+/// Writes a recursive object, keeping track of the nodes we're partway through
+/// on an explicit stack.
+struct Printer<'a, 'b> {
+    f: &'a mut Formatter<'b>,
+    pretty: bool,
+    max_depth: usize,
+    max_children: usize,
+}
+
+impl<'a, 'b> Printer<'a, 'b> {
+    fn new(f: &'a mut Formatter<'b>, max_depth: usize, max_children: usize) -> Self {
+        Self {
+            pretty: f.alternate(),
+            f,
+            max_depth,
+            max_children,
+        }
+    }
+
+    fn print<K>(&mut self, root: &K) -> fmt::Result
+    where
+        K: IntoOptionInner + Deref<Target = K::Inner>,
+        K::Inner: RecursiveDebug<Container = K>,
+    {
+        let mut stack = Vec::new();
+        stack.extend(self.enter(root, 0)?);
+        while let Some(open) = stack.last_mut() {
+            let depth = open.depth + 1;
+            let child = match open.children.next() {
+                Some(_) if open.written_children == self.max_children => {
+                    // Skip the rest of the children.
+                    while open.children.next().is_some() {}
+                    self.begin_item(open.items, depth)?;
+                    open.items += 1;
+                    self.f.write_str("..")?;
+                    self.end_item()?;
+                    continue;
+                }
+                Some(child) => child,
+                None => {
+                    let depth = open.depth;
+                    stack.pop();
+                    self.close(depth)?;
+                    if !stack.is_empty() {
+                        self.end_item()?;
+                    }
+                    continue;
+                }
+            };
+
+            self.begin_item(open.items, depth)?;
+            open.items += 1;
+            open.written_children += 1;
+            if depth > self.max_depth {
+                self.f.write_str("..")?;
+                self.end_item()?;
+                continue;
+            }
+            match self.enter(child, depth)? {
+                Some(open) => stack.push(open),
+                None => self.end_item()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a node's name and fields.
+    /// If it has any fields or children, the node is left open, and returned.
+    fn enter<'n, K>(
+        &mut self,
+        container: &'n K,
+        depth: usize,
+    ) -> Result<Option<Open<impl Iterator<Item = &'n K> + 'n>>, fmt::Error>
+    where
+        K: IntoOptionInner + Deref<Target = K::Inner>,
+        K::Inner: RecursiveDebug<Container = K>,
+    {
+        let node = &**container;
+        self.f.write_str(node.debug_name())?;
+        let mut fields = node.debug_fields().peekable();
+        let mut children = node.children().peekable();
+        if fields.peek().is_none() && children.peek().is_none() {
+            return Ok(None);
+        }
+
+        self.f.write_str(if self.pretty { "(\n" } else { "(" })?;
+        let mut items = 0;
+        for field in fields {
+            self.begin_item(items, depth + 1)?;
+            items += 1;
+            if self.pretty {
+                let mut indented = Indented {
+                    f: self.f,
+                    depth: depth + 1,
+                    on_newline: false,
+                };
+                write!(indented, "{field:#?}")?;
+            } else {
+                field.fmt(self.f)?;
+            }
+            self.end_item()?;
+        }
+        Ok(Some(Open {
+            children,
+            depth,
+            items,
+            written_children: 0,
+        }))
+    }
+
+    /// Prepares to write a field or child of a node.
+    fn begin_item(&mut self, items: usize, depth: usize) -> fmt::Result {
+        if self.pretty {
+            write_indent(self.f, depth)
+        } else if items > 0 {
+            self.f.write_str(", ")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Finishes writing a field or child of a node.
+    fn end_item(&mut self) -> fmt::Result {
+        if self.pretty {
+            self.f.write_str(",\n")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Closes a node that was left open by [Printer::enter].
+    fn close(&mut self, depth: usize) -> fmt::Result {
+        if self.pretty {
+            write_indent(self.f, depth)?;
+        }
+        self.f.write_str(")")
+    }
+}
+
+fn write_indent(f: &mut impl Write, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_str("    ")?;
+    }
+    Ok(())
+}
+
+/// Indents every line written to it after the first.
+struct Indented<'a, 'b> {
+    f: &'a mut Formatter<'b>,
+    depth: usize,
+    on_newline: bool,
+}
+
+impl Write for Indented<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.on_newline {
+                write_indent(self.f, self.depth)?;
+            }
+            self.on_newline = line.ends_with('\n');
+            self.f.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, format};
+    use core::fmt::Debug;
+
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        FlatDrop, Recursive, RecursiveDebug, RecursiveRef,
+    };
+
+    #[test]
+    fn test_debug_large_natural() {
+        on_small_stack(|| {
+            let nat = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let output = format!("{nat:?}");
+            assert_eq!(
+                output.len(),
+                STACK_SIZE * 100 * "Succ()".len() + "Zero".len()
+            );
+        });
+    }
+
+    /// A binary tree with labelled nodes.
+    enum Tree {
+        Leaf,
+        Node((u8, &'static str), FlatDrop<Box<Tree>>, FlatDrop<Box<Tree>>),
+    }
+
+    impl Recursive for Tree {
+        type Container = Box<Tree>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Tree::Leaf => None,
+                Tree::Node(_, left, right) => Some([left.into_inner(), right.into_inner()]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursiveRef for Tree {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            match self {
+                Tree::Leaf => None,
+                Tree::Node(_, left, right) => Some([&**left, &**right]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    impl RecursiveDebug for Tree {
+        fn debug_name(&self) -> &str {
+            match self {
+                Tree::Leaf => "Leaf",
+                Tree::Node(..) => "Node",
+            }
+        }
+
+        fn debug_fields(&self) -> impl Iterator<Item = &dyn Debug> {
+            match self {
+                Tree::Leaf => None,
+                Tree::Node(label, ..) => Some(label as &dyn Debug),
+            }
+            .into_iter()
+        }
+    }
+
+    fn node(label: u8, left: Tree, right: Tree) -> Tree {
+        Tree::Node(
+            (label, "x"),
+            FlatDrop::new_boxed(left),
+            FlatDrop::new_boxed(right),
+        )
+    }
+
+    #[test]
+    fn test_debug_tree() {
+        let tree = FlatDrop::new_boxed(node(1, node(2, Tree::Leaf, Tree::Leaf), Tree::Leaf));
+        assert_eq!(
+            format!("{tree:?}"),
+            r#"Node((1, "x"), Node((2, "x"), Leaf, Leaf), Leaf)"#
+        );
+        assert_eq!(
+            format!("{tree:#?}"),
+            r#"Node(
+    (
+        1,
+        "x",
+    ),
+    Node(
+        (
+            2,
+            "x",
+        ),
+        Leaf,
+        Leaf,
+    ),
+    Leaf,
+)"#
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(0, 1)),
+            r#"Node((1, "x"), .., ..)"#
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(1, 2)),
+            r#"Node((1, "x"), Node((2, "x"), .., ..), Leaf)"#
+        );
+    }
+}
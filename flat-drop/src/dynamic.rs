@@ -0,0 +1,245 @@
+//! Flat drops of trait objects.
+//!
+//! [Recursive] can't be made into a trait object, because
+//! [Recursive::destruct] takes `self` by value and returns `impl Iterator`.
+//! [RecursiveDyn] does the same job in a form that can, so trees of
+//! `Box<dyn RecursiveDyn>` holding different types of node can be flat dropped.
+
+use alloc::{boxed::Box, collections::VecDeque};
+
+use crate::{IntoOptionInner, Recursive};
+
+/// A version of [Recursive] for nodes held as `Box<dyn RecursiveDyn>`.
+///
+/// Trees that are sent between threads can hold their nodes as
+/// `Box<dyn RecursiveDyn + Send>` instead, which are passed to `out` in the same way.
+///
+/// ```
+/// use flat_drop::{FlatDrop, RecursiveDyn};
+///
+/// struct Group {
+///     children: Vec<FlatDrop<Box<dyn RecursiveDyn>>>,
+/// }
+///
+/// impl RecursiveDyn for Group {
+///     fn destruct_boxed(self: Box<Self>, out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {
+///         self.children.into_iter().for_each(|child| out(child.into_inner()));
+///     }
+/// }
+///
+/// struct Mesh {
+///     vertices: Vec<[f32; 3]>,
+/// }
+///
+/// impl RecursiveDyn for Mesh {
+///     fn destruct_boxed(self: Box<Self>, _out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {}
+/// }
+///
+/// let mesh: Box<dyn RecursiveDyn> = Box::new(Mesh { vertices: Vec::new() });
+/// let scene: Box<dyn RecursiveDyn> = Box::new(Group {
+///     children: vec![FlatDrop::new(mesh)],
+/// });
+/// drop(FlatDrop::new(scene));
+/// ```
+pub trait RecursiveDyn {
+    /// Decomposes this node, passing the container of each of its recursive parts
+    /// to `out`. See [Recursive::destruct].
+    fn destruct_boxed(self: Box<Self>, out: &mut dyn FnMut(Box<dyn RecursiveDyn>));
+}
+
+/// A node of a tree of trait objects, which a flat drop is about to take apart.
+/// This is the [IntoOptionInner::Inner] of `Box<dyn RecursiveDyn>`.
+pub struct DynNode(Box<dyn RecursiveDyn>);
+
+/// The node stays in its [Box], since a trait object can't be moved out of it.
+impl IntoOptionInner for Box<dyn RecursiveDyn> {
+    type Inner = DynNode;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        Some(DynNode(self))
+    }
+}
+
+/// A node that can be sent between threads is taken apart like any other,
+/// so `FlatDrop<Box<dyn RecursiveDyn + Send>>` can be dropped in the background.
+impl IntoOptionInner for Box<dyn RecursiveDyn + Send> {
+    type Inner = DynNode;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        Some(DynNode(self))
+    }
+}
+
+impl From<Box<dyn RecursiveDyn + Send>> for Box<dyn RecursiveDyn> {
+    fn from(node: Box<dyn RecursiveDyn + Send>) -> Self {
+        node
+    }
+}
+
+impl Recursive for DynNode {
+    type Container = Box<dyn RecursiveDyn>;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        let mut children = Children::default();
+        self.0.destruct_boxed(&mut |child| children.push(child));
+        children
+    }
+}
+
+/// How many children of a node are kept without allocating.
+const INLINE_CHILDREN: usize = 4;
+
+/// The children of a [DynNode]. The first few are kept inline, so that taking apart
+/// a node with only a few children doesn't allocate.
+#[derive(Default)]
+struct Children {
+    inline: [Option<Box<dyn RecursiveDyn>>; INLINE_CHILDREN],
+    /// The number of inline children.
+    len: usize,
+    /// The index of the next inline child to yield.
+    next: usize,
+    rest: VecDeque<Box<dyn RecursiveDyn>>,
+}
+
+impl Children {
+    fn push(&mut self, child: Box<dyn RecursiveDyn>) {
+        if self.len < INLINE_CHILDREN {
+            self.inline[self.len] = Some(child);
+            self.len += 1;
+            return;
+        }
+        if self.rest.try_reserve(1).is_ok() {
+            self.rest.push_back(child);
+        } else {
+            // As a last resort when there's no memory, the child is dropped directly.
+            // Each `FlatDrop` inside it starts its own flat drop.
+            drop(child);
+        }
+    }
+}
+
+impl Iterator for Children {
+    type Item = Box<dyn RecursiveDyn>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next < self.len {
+            self.next += 1;
+            self.inline[self.next - 1].take()
+        } else {
+            self.rest.pop_front()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len - self.next + self.rest.len();
+        (len, Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::Cell;
+
+    use super::RecursiveDyn;
+    #[cfg(feature = "std")]
+    use crate::tests::ReportThread;
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        FlatDrop,
+    };
+
+    /// A node with any number of children.
+    struct Group {
+        children: Vec<FlatDrop<Box<dyn RecursiveDyn>>>,
+    }
+
+    impl RecursiveDyn for Group {
+        fn destruct_boxed(self: Box<Self>, out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {
+            self.children
+                .into_iter()
+                .for_each(|child| out(child.into_inner()));
+        }
+    }
+
+    /// A node that applies to one child, and counts how many of its kind are dropped.
+    struct Transform {
+        child: FlatDrop<Box<dyn RecursiveDyn>>,
+        dropped: Rc<Cell<usize>>,
+    }
+
+    impl RecursiveDyn for Transform {
+        fn destruct_boxed(self: Box<Self>, out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {
+            self.dropped.set(self.dropped.get() + 1);
+            out(self.child.into_inner());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl RecursiveDyn for ReportThread {
+        fn destruct_boxed(self: Box<Self>, _out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {}
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_dyn_send_in_background() {
+        use std::{sync::mpsc, thread};
+
+        /// A node with any number of children, which can be sent to another thread.
+        struct SendGroup {
+            children: Vec<FlatDrop<Box<dyn RecursiveDyn + Send>>>,
+        }
+
+        impl RecursiveDyn for SendGroup {
+            fn destruct_boxed(self: Box<Self>, out: &mut dyn FnMut(Box<dyn RecursiveDyn>)) {
+                self.children
+                    .into_iter()
+                    .for_each(|child| out(child.into_inner()));
+            }
+        }
+
+        on_small_stack(|| {
+            let (sender, receiver) = mpsc::channel();
+            let node = |node: Box<dyn RecursiveDyn + Send>| FlatDrop::new(node);
+            let leaf = || {
+                node(Box::new(SendGroup {
+                    children: Vec::new(),
+                }))
+            };
+            // Each group has more children than are kept without allocating.
+            let report = node(Box::new(ReportThread(sender)));
+            let tree = (0..STACK_SIZE * 100).fold(report, |tree, _| {
+                let mut children = Vec::from([tree]);
+                children.extend((0..5).map(|_| leaf()));
+                node(Box::new(SendGroup { children }))
+            });
+            tree.drop_in_background();
+            assert_ne!(receiver.recv().unwrap(), thread::current().id());
+        });
+    }
+
+    #[test]
+    fn test_dyn_large_tree() {
+        on_small_stack(|| {
+            let dropped = Rc::new(Cell::new(0));
+            let leaf = || {
+                FlatDrop::new(Box::new(Group {
+                    children: Vec::new(),
+                }) as Box<_>)
+            };
+            let tree = (0..STACK_SIZE * 100).fold(leaf(), |tree, _| {
+                let transform: Box<dyn RecursiveDyn> = Box::new(Transform {
+                    child: tree,
+                    dropped: Rc::clone(&dropped),
+                });
+                let group = Group {
+                    children: Vec::from([FlatDrop::new(transform), leaf()]),
+                };
+                FlatDrop::new(Box::new(group) as Box<_>)
+            });
+            let stats = tree.drop_counted();
+            assert_eq!(stats.freed, STACK_SIZE * 100 * 3 + 1);
+            assert_eq!(dropped.get(), STACK_SIZE * 100);
+        });
+    }
+}
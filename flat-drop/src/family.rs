@@ -0,0 +1,259 @@
+//! Families of mutually recursive types.
+//!
+//! [Recursive::destruct](crate::Recursive::destruct) can only yield one type of
+//! container, so types that contain each other, such as the expressions and
+//! statements of a syntax tree, can't each use their own container. Instead, they
+//! share an enum with a variant for each member's container, which [family!] generates.
+//! Each member's container converts into it, so fields can still hold a
+//! `FlatDrop<Box<Expr>>` rather than the enum.
+
+/// Declares a family of mutually recursive types, which are taken apart together
+/// by one flat drop.
+///
+/// This generates two enums, each with a variant for each member of the family.
+/// The first holds a container of a member, and is the [Recursive::Container] of
+/// every member. Each member's container converts into it with [From]. The second
+/// holds a member itself, and implements [Recursive] by forwarding to the member.
+///
+/// The second enum's [Recursive] implementation forwards [Recursive::destruct],
+/// [Recursive::before_destruct] and [Recursive::spare_link] to the member, and the
+/// first enum lends out each member's spare link, so a flat drop that runs out of
+/// memory can still link nodes together. Since the members may disagree, it doesn't
+/// forward [Recursive::TRAVERSAL] or [Recursive::REENTRANT], and uses their defaults
+/// instead, so nested drops don't join a flat drop of the family, and
+/// [Traversal::Threaded](crate::Traversal::Threaded) can't be used.
+/// Traits like [Clone] that need a type's children to be of the same type as itself
+/// aren't implemented for `FlatDrop`s of members of a family.
+///
+/// [Recursive]: crate::Recursive
+/// [Recursive::Container]: crate::Recursive::Container
+/// [Recursive::TRAVERSAL]: crate::Recursive::TRAVERSAL
+/// [Recursive::REENTRANT]: crate::Recursive::REENTRANT
+/// [Recursive::destruct]: crate::Recursive::destruct
+/// [Recursive::before_destruct]: crate::Recursive::before_destruct
+/// [Recursive::spare_link]: crate::Recursive::spare_link
+///
+/// ```
+/// use flat_drop::{family, FlatDrop, Recursive};
+///
+/// enum Expr {
+///     Int(i64),
+///     Block(Vec<FlatDrop<Box<Stmt>>>),
+/// }
+///
+/// enum Stmt {
+///     Expr(FlatDrop<Box<Expr>>),
+///     Return(FlatDrop<Box<Expr>>),
+/// }
+///
+/// family! {
+///     /// A node of a syntax tree, in a `Box`.
+///     enum Node {
+///         Expr(Box<Expr>),
+///         Stmt(Box<Stmt>),
+///     }
+///
+///     /// A node of a syntax tree.
+///     enum NodeInner;
+/// }
+///
+/// impl Recursive for Expr {
+///     type Container = Node;
+///
+///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+///         match self {
+///             Expr::Int(_) => Vec::new(),
+///             Expr::Block(stmts) => stmts.into_iter().map(|stmt| stmt.into_inner().into()).collect(),
+///         }
+///         .into_iter()
+///     }
+/// }
+///
+/// impl Recursive for Stmt {
+///     type Container = Node;
+///
+///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+///         match self {
+///             Stmt::Expr(expr) | Stmt::Return(expr) => std::iter::once(expr.into_inner().into()),
+///         }
+///     }
+/// }
+///
+/// let block = FlatDrop::new_boxed(Expr::Block(vec![FlatDrop::new_boxed(Stmt::Return(
+///     FlatDrop::new_boxed(Expr::Int(42)),
+/// ))]));
+/// drop(block);
+/// ```
+#[macro_export]
+macro_rules! family {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $family:ident {
+            $($variant:ident($container:ty)),+ $(,)?
+        }
+
+        $(#[$inner_meta:meta])*
+        $inner_vis:vis enum $inner:ident;
+    ) => {
+        $(#[$meta])*
+        $vis enum $family {
+            $($variant($container),)+
+        }
+
+        $(#[$inner_meta])*
+        $inner_vis enum $inner {
+            $($variant(<$container as $crate::IntoOptionInner>::Inner),)+
+        }
+
+        $(
+            impl ::core::convert::From<$container> for $family {
+                fn from(container: $container) -> Self {
+                    $family::$variant(container)
+                }
+            }
+        )+
+
+        impl $crate::IntoOptionInner for $family {
+            type Inner = $inner;
+
+            fn into_option_inner(self) -> ::core::option::Option<Self::Inner> {
+                match self {
+                    $(
+                        $family::$variant(container) => {
+                            $crate::IntoOptionInner::into_option_inner(container)
+                                .map($inner::$variant)
+                        }
+                    )+
+                }
+            }
+
+            fn inner_spare_link(
+                &mut self,
+            ) -> ::core::option::Option<&mut ::core::option::Option<Self>> {
+                match self {
+                    $(
+                        $family::$variant(container) => {
+                            $crate::IntoOptionInner::inner_spare_link(container)
+                        }
+                    )+
+                }
+            }
+        }
+
+        impl $crate::Recursive for $inner {
+            type Container = $family;
+
+            fn destruct(self) -> impl ::core::iter::Iterator<Item = Self::Container> {
+                /// The parts of whichever member this was.
+                enum Parts<$($variant),+> {
+                    $($variant($variant),)+
+                }
+
+                impl<$($variant),+> ::core::iter::Iterator for Parts<$($variant),+>
+                where
+                    $($variant: ::core::iter::Iterator<Item = $family>,)+
+                {
+                    type Item = $family;
+
+                    fn next(&mut self) -> ::core::option::Option<$family> {
+                        match self {
+                            $(Parts::$variant(parts) => parts.next(),)+
+                        }
+                    }
+
+                    fn size_hint(&self) -> (usize, ::core::option::Option<usize>) {
+                        match self {
+                            $(Parts::$variant(parts) => parts.size_hint(),)+
+                        }
+                    }
+                }
+
+                match self {
+                    $($inner::$variant(value) => Parts::$variant($crate::Recursive::destruct(value)),)+
+                }
+            }
+
+            fn before_destruct(&mut self) {
+                match self {
+                    $($inner::$variant(value) => $crate::Recursive::before_destruct(value),)+
+                }
+            }
+
+            fn spare_link(
+                &mut self,
+            ) -> ::core::option::Option<&mut ::core::option::Option<Self::Container>> {
+                match self {
+                    $($inner::$variant(value) => $crate::Recursive::spare_link(value),)+
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        FlatDrop, Recursive,
+    };
+
+    /// An expression, which may contain statements.
+    enum Expr {
+        Int(#[allow(dead_code)] i64),
+        Block(Vec<FlatDrop<Rc<Stmt>>>, FlatDrop<Box<Expr>>),
+    }
+
+    /// A statement, which may contain expressions.
+    enum Stmt {
+        Let(FlatDrop<Box<Expr>>),
+    }
+
+    crate::family! {
+        enum Node {
+            Expr(Box<Expr>),
+            Stmt(Rc<Stmt>),
+        }
+
+        enum NodeInner;
+    }
+
+    impl Recursive for Expr {
+        type Container = Node;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            let (stmts, value) = match self {
+                Expr::Int(_) => (Vec::new(), None),
+                Expr::Block(stmts, value) => (stmts, Some(value)),
+            };
+            let stmts = stmts.into_iter().map(|stmt| stmt.into_inner().into());
+            stmts.chain(value.map(|value| value.into_inner().into()))
+        }
+    }
+
+    impl Recursive for Stmt {
+        type Container = Node;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Stmt::Let(expr) => core::iter::once(expr.into_inner().into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutually_recursive() {
+        on_small_stack(|| {
+            let shared = Rc::new(Stmt::Let(FlatDrop::new_boxed(Expr::Int(0))));
+            let tree = (0..STACK_SIZE * 100).fold(Expr::Int(0), |expr, _| {
+                let stmt = FlatDrop::new_rc(Stmt::Let(FlatDrop::new_boxed(expr)));
+                let shared = FlatDrop::new(Rc::clone(&shared));
+                Expr::Block(Vec::from([stmt, shared]), FlatDrop::new_boxed(Expr::Int(1)))
+            });
+            drop(std::hint::black_box(FlatDrop::new_boxed(tree)));
+            // The last reference to the shared statement is ours.
+            assert_eq!(Rc::strong_count(&shared), 1);
+        });
+    }
+}
@@ -0,0 +1,275 @@
+//! A `Vec` of children that is dropped iteratively.
+
+use alloc::{slice, vec, vec::Vec};
+use core::{
+    ops::{Deref, DerefMut, Index, IndexMut},
+    slice::SliceIndex,
+};
+
+use crate::{FlatDropMany, Recursive};
+
+/// A `Vec<T>` that drops nested `FlatVec`s iteratively, for recursive types that
+/// store their children directly, without a `Box` around each one.
+///
+/// `T` implements [Recursive] with `Vec<T>` as its container, usually yielding the
+/// contents of each of its `FlatVec`s with [FlatVec::into_vec].
+/// Read-only and in-place access to the elements is through the slice that a
+/// `FlatVec` dereferences to.
+///
+/// ```
+/// use flat_drop::{FlatVec, Recursive};
+///
+/// /// A rose tree.
+/// struct Node {
+///     kids: FlatVec<Node>,
+/// }
+///
+/// impl Recursive for Node {
+///     type Container = Vec<Node>;
+///
+///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+///         std::iter::once(self.kids.into_vec())
+///     }
+/// }
+///
+/// let mut tree = Node { kids: FlatVec::new() };
+/// for _ in 0..100_000 {
+///     let mut parent = Node { kids: FlatVec::new() };
+///     parent.kids.push(tree);
+///     tree = parent;
+/// }
+/// assert_eq!(tree.kids.len(), 1);
+/// drop(tree);
+/// ```
+pub struct FlatVec<T>(FlatDropMany<Vec<T>>)
+where
+    T: Recursive<Container = Vec<T>>;
+
+impl<T> Default for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    pub const fn new() -> Self {
+        Self(FlatDropMany::new(Vec::new()))
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(FlatDropMany::new(Vec::with_capacity(capacity)))
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.0.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+
+    pub fn insert(&mut self, index: usize, value: T) {
+        self.0.insert(index, value);
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        self.0.remove(index)
+    }
+
+    /// Removes every element, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        // Each element's own `FlatVec`s drop iteratively, so this doesn't recurse.
+        self.0.clear();
+    }
+
+    /// Returns the underlying `Vec`, which will no longer be dropped iteratively.
+    pub fn into_vec(self) -> Vec<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T> Deref for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
+impl<T, I> Index<I> for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+    I: SliceIndex<[T]>,
+{
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &I::Output {
+        &self.0[index]
+    }
+}
+
+impl<T, I> IndexMut<I> for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+    I: SliceIndex<[T]>,
+{
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        &mut self.0[index]
+    }
+}
+
+impl<T> From<Vec<T>> for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    fn from(vec: Vec<T>) -> Self {
+        Self(FlatDropMany::new(vec))
+    }
+}
+
+impl<T> FromIterator<T> for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(Vec::from_iter(iter))
+    }
+}
+
+impl<T> Extend<T> for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+/// The elements are moved out, so each one is dropped on its own, iteratively,
+/// if it isn't kept.
+impl<T> IntoIterator for FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    type Item = T;
+    type IntoIter = vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut FlatVec<T>
+where
+    T: Recursive<Container = Vec<T>>,
+{
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::FlatVec;
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        Recursive,
+    };
+
+    /// A rose tree with a value at each node.
+    struct Node {
+        value: u32,
+        kids: FlatVec<Node>,
+    }
+
+    impl Recursive for Node {
+        type Container = Vec<Node>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            core::iter::once(self.kids.into_vec())
+        }
+    }
+
+    fn leaf(value: u32) -> Node {
+        Node {
+            value,
+            kids: FlatVec::new(),
+        }
+    }
+
+    #[test]
+    fn test_flat_vec() {
+        let mut kids: FlatVec<Node> = (0..3).map(leaf).collect();
+        kids.push(leaf(3));
+        kids[0].kids.extend([leaf(4), leaf(5)]);
+        assert_eq!(kids.remove(1).value, 1);
+        assert_eq!(kids.pop().map(|node| node.value), Some(3));
+
+        let values: Vec<u32> = kids.iter().map(|node| node.value).collect();
+        assert_eq!(values, [0, 2]);
+        let values: Vec<u32> = (&kids[0].kids).into_iter().map(|node| node.value).collect();
+        assert_eq!(values, [4, 5]);
+        for node in &mut kids {
+            node.value += 10;
+        }
+        let values: Vec<u32> = kids.into_iter().map(|node| node.value).collect();
+        assert_eq!(values, [10, 12]);
+    }
+
+    #[test]
+    fn test_flat_vec_large_tree() {
+        on_small_stack(|| {
+            let deep = || {
+                (0..STACK_SIZE * 100).fold(leaf(0), |tree, i| {
+                    let mut node = leaf(i as u32);
+                    node.kids.push(tree);
+                    node.kids.push(leaf(0));
+                    node
+                })
+            };
+            drop(std::hint::black_box(deep()));
+
+            // Clearing drops the removed elements iteratively too.
+            let mut kids = FlatVec::with_capacity(4);
+            kids.push(deep());
+            kids.clear();
+            assert!(kids.is_empty());
+            assert!(kids.0.capacity() >= 4);
+        });
+    }
+}
@@ -0,0 +1,82 @@
+//! Hashing recursive objects iteratively.
+
+use alloc::vec;
+use core::{
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use crate::{FlatDrop, IntoOptionInner, RecursiveRef};
+
+/// A recursive type whose values can be hashed one node at a time.
+///
+/// The hash of a value is made from the shallow hash of each node in pre-order,
+/// with each node's number of children written after the last of its descendants,
+/// so that values of different shapes hash differently.
+/// For this to be consistent with [RecursivePartialEq](crate::RecursivePartialEq),
+/// nodes for which `shallow_eq` holds must have the same shallow hash.
+pub trait RecursiveHash: RecursiveRef {
+    /// Hashes the parts of this node that aren't children.
+    fn shallow_hash<H: Hasher>(&self, state: &mut H);
+}
+
+/// Hashes `root` and everything inside it, without recursion.
+fn hash_iterative<K, H>(root: &K, state: &mut H)
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveHash<Container = K>,
+    H: Hasher,
+{
+    root.shallow_hash(state);
+
+    // The remaining children of each node that we're partway through hashing,
+    // and how many of its children we've hashed so far.
+    let mut stack = vec![(root.children(), 0usize)];
+    while let Some((children, count)) = stack.last_mut() {
+        match children.next() {
+            Some(child) => {
+                *count += 1;
+                child.shallow_hash(state);
+                stack.push((child.children(), 0));
+            }
+            None => {
+                state.write_usize(*count);
+                stack.pop();
+            }
+        }
+    }
+}
+
+impl<K> Hash for FlatDrop<K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveHash<Container = K>,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_iterative(&**self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        FlatDrop,
+    };
+
+    #[test]
+    fn test_hash_large_natural() {
+        on_small_stack(|| {
+            let mut set = HashSet::new();
+            for n in [2, 1, 2, 3] {
+                set.insert(FlatDrop::new_boxed(Natural::from_usize(
+                    n * STACK_SIZE * 20,
+                )));
+            }
+            assert_eq!(set.len(), 3);
+            assert!(set.contains(&FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 20))));
+        });
+    }
+}
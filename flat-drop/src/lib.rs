@@ -57,21 +57,228 @@
 //!     .join()
 //!     .unwrap();
 //! ```
+//!
+//! # Deriving `Recursive`
+//!
+//! With the `derive` feature enabled, `#[derive(Recursive)]` generates the
+//! [Recursive] implementation, yielding the container of every `FlatDrop` field
+//! (including those inside `Vec`s, `Option`s and tuples).
+//!
+//! ```
+//! # #[cfg(feature = "derive")] {
+//! use flat_drop::{FlatDrop, Recursive};
+//!
+//! #[derive(Recursive)]
+//! enum Tree {
+//!     Leaf(u32),
+//!     Node(Vec<FlatDrop<Box<Tree>>>),
+//! }
+//! # }
+//! ```
+//!
+//! [RecursiveRef] and [RecursiveDebug] can be derived too, which implements [Debug]
+//! for `FlatDrop<Box<Tree>>`, so that types holding one can derive [Debug] themselves.
+//!
+//! ```
+//! # #[cfg(feature = "derive")] {
+//! use flat_drop::{FlatDrop, Recursive, RecursiveDebug, RecursiveRef};
+//!
+//! #[derive(Recursive, RecursiveRef, RecursiveDebug, Debug)]
+//! enum Tree {
+//!     Leaf(u32),
+//!     Node(Vec<FlatDrop<Box<Tree>>>),
+//! }
+//!
+//! let tree = Tree::Node(vec![FlatDrop::new_boxed(Tree::Leaf(1))]);
+//! assert_eq!(format!("{tree:?}"), "Node([Leaf(1)])");
+//! assert_eq!(format!("{:?}", FlatDrop::new_boxed(tree)), "Node(Leaf(1))");
+//! # }
+//! ```
+//!
+//! # Children stored directly
+//!
+//! To avoid allocating a `Box` for each child, children can be kept directly in a
+//! `Vec<T>`, `Box<[T]>` or `Option<Box<T>>`, wrapped in [FlatDropMany].
+//! [FlatVec] is a ready-made `Vec` of children.
+//!
+//! # Mutually recursive types
+//!
+//! Types that contain each other, like the expressions and statements of a syntax
+//! tree, can be declared as a [family!], which lets one flat drop take apart
+//! all of them.
+//!
+//! # Trait objects
+//!
+//! [Recursive] isn't dyn-compatible, so trees whose nodes are trait objects implement
+//! [RecursiveDyn] instead, and use `FlatDrop<Box<dyn RecursiveDyn>>`.
+//!
+//! # Other containers
+//!
+//! Besides [Box], [Rc] and [Arc], [IntoOptionInner] is implemented for
+//! [Cow](alloc::borrow::Cow), where only owned data is dropped, and for
+//! [AnyContainer], which is either a [Box] or an [Arc]. The `triomphe`,
+//! `servo_arc`, `bumpalo` and `rclite` features implement it for `triomphe::Arc`,
+//! `triomphe::UniqueArc`, `servo_arc::Arc`, `bumpalo::boxed::Box`, `rclite::Rc` and
+//! `rclite::Arc`. On a nightly compiler, the `nightly` feature implements it for
+//! `UniqueRc`.
+//!
+//! # `no_std`
+//!
+//! The `std` feature is enabled by default. Without it, this crate is `#![no_std]`
+//! and only needs `alloc`. Dropping in the background, `DropQueue::step_for`,
+//! reusing worklist allocations and joining nested drops (see [Recursive::REENTRANT])
+//! all need `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "nightly", feature(unique_rc_arc))]
 
-use std::{
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+#[cfg(target_has_atomic = "ptr")]
+use alloc::sync::Arc;
+use alloc::{boxed::Box, collections::VecDeque, rc::Rc, vec::Vec};
+use core::{
+    cell::UnsafeCell,
     fmt::Display,
     mem::ManuallyDrop,
     ops::{Deref, DerefMut},
-    rc::Rc,
-    sync::Arc,
+    ptr::NonNull,
 };
 
+#[cfg(feature = "std")]
+mod background;
+mod clone;
+mod cmp;
+mod debug;
+mod dynamic;
+mod family;
+mod flat_vec;
+mod hash;
+mod many;
+#[cfg(target_has_atomic = "ptr")]
+mod mixed;
+mod pointers;
+mod queue;
+mod reentrant;
+mod stats;
+#[cfg(feature = "serde")]
+mod table;
+mod traversal;
+mod worklist;
+
+pub use clone::{CloneContainer, RecursiveClone};
+pub use cmp::{RecursiveEq, RecursiveOrd, RecursivePartialEq, RecursivePartialOrd};
+pub use debug::{DebugTruncated, RecursiveDebug};
+pub use dynamic::{DynNode, RecursiveDyn};
+pub use flat_vec::FlatVec;
+pub use hash::RecursiveHash;
+pub use many::{FlatDropMany, IntoInners};
+#[cfg(target_has_atomic = "ptr")]
+pub use mixed::AnyContainer;
+pub use queue::DropQueue;
+pub use reentrant::Reentrant;
+pub use stats::{DropObserver, DropStats};
+#[cfg(feature = "serde")]
+pub use table::{FlatDeserialize, FlatSerialize, RecursiveDeserialize, RecursiveSerialize};
+pub use traversal::Traversal;
+use worklist::Worklist;
+
+#[cfg(feature = "derive")]
+pub use flat_drop_derive::{Recursive, RecursiveDebug, RecursiveRef};
+
 /// The [Recursive::destruct] function decomposes an object into some component parts.
 /// Usually, [Recursive::Container] is something like `Box<Self>` or `Arc<Self>`.
 pub trait Recursive {
     type Container;
 
+    /// Whether drops of `FlatDrop<Self::Container>` that begin while another one is
+    /// in progress on the same thread should join the drop already in progress.
+    ///
+    /// If [Recursive::destruct] doesn't extract every `FlatDrop` from `self`,
+    /// the ones left behind are dropped in the middle of the iterative procedure,
+    /// and each starts its own. Setting this to `Some(Reentrant::new())` defers them
+    /// onto the outer drop's worklist instead, so that they don't use any more stack.
+    /// This requires `Self::Container: 'static`.
+    ///
+    /// ```
+    /// use flat_drop::{FlatDrop, Recursive, Reentrant};
+    ///
+    /// struct Node {
+    ///     first: FlatDrop<Box<Node>>,
+    ///     // Never extracted by `destruct`.
+    ///     rest: Vec<FlatDrop<Box<Node>>>,
+    /// }
+    ///
+    /// impl Recursive for Node {
+    ///     type Container = Box<Node>;
+    ///
+    ///     const REENTRANT: Option<Reentrant<Self::Container>> = Some(Reentrant::new());
+    ///
+    ///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+    ///         std::iter::once(self.first.into_inner())
+    ///     }
+    /// }
+    /// ```
+    const REENTRANT: Option<Reentrant<Self::Container>> = None;
+
+    /// The order in which a flat drop takes apart the nodes of an object.
+    /// Depth-first by default; see [Traversal] for the alternatives.
+    const TRAVERSAL: Traversal = Traversal::DepthFirst;
+
     fn destruct(self) -> impl Iterator<Item = Self::Container>;
+
+    /// Called on each node just before a flat drop takes it apart with
+    /// [Recursive::destruct], for cleanup that must happen in a particular order.
+    ///
+    /// A node is always visited before its children, in the order given by
+    /// [Recursive::TRAVERSAL], so a chain of nodes is visited from the root down.
+    /// Nodes in shared containers that aren't freed are not visited.
+    fn before_destruct(&mut self) {}
+
+    /// Returns an empty slot in this node that [Traversal::Threaded] may use to link
+    /// together the containers waiting to be dropped, or `None` if there isn't one.
+    /// Other traversals use it too if their worklist can't grow because memory has
+    /// run out. Without it, nodes that don't fit are dropped directly, which uses
+    /// more stack.
+    ///
+    /// The slot must be empty whenever the node isn't being dropped, and must be the
+    /// same each time this is called. Anything left in it is dropped along with the node.
+    ///
+    /// ```
+    /// use flat_drop::{FlatDrop, Recursive, Traversal};
+    ///
+    /// struct Node {
+    ///     children: Vec<FlatDrop<Box<Node>>>,
+    ///     link: Option<Box<Node>>,
+    /// }
+    ///
+    /// impl Recursive for Node {
+    ///     type Container = Box<Node>;
+    ///
+    ///     const TRAVERSAL: Traversal = Traversal::Threaded;
+    ///
+    ///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+    ///         let children = self.children.into_iter().map(FlatDrop::into_inner);
+    ///         children.chain(self.link)
+    ///     }
+    ///
+    ///     fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+    ///         Some(&mut self.link)
+    ///     }
+    /// }
+    /// ```
+    fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+        None
+    }
+}
+
+/// A recursive type whose children can be inspected without taking it apart.
+/// This is the basis of the iterative implementations of traits like [Clone] for [FlatDrop].
+pub trait RecursiveRef: Recursive {
+    /// Iterates over the containers that [Recursive::destruct] would return, in order.
+    fn children(&self) -> impl Iterator<Item = &Self::Container>;
 }
 
 /// A trait for a smart pointer that contains (at most) a single value.
@@ -84,6 +291,57 @@ pub trait IntoOptionInner {
     /// If `Self == Box`, this will always return `Some(*self)`.
     /// If `Self == Arc`, this is `Arc::into_inner`.
     fn into_option_inner(self) -> Option<Self::Inner>;
+
+    /// Returns a mutable reference to the internal value, if this is its only owner.
+    /// This is used by [Traversal::Threaded], and returns `None` by default.
+    ///
+    /// If `Self == Arc`, this is `Arc::get_mut`.
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        None
+    }
+
+    /// Returns the [Recursive::spare_link] of the internal value, if this is its only owner.
+    /// By default, this looks for it through [IntoOptionInner::inner_mut], but containers
+    /// that can't lend out their internal value, like those of a [family!], can still
+    /// lend out its spare link.
+    fn inner_spare_link(&mut self) -> Option<&mut Option<<Self::Inner as Recursive>::Container>>
+    where
+        Self::Inner: Recursive,
+    {
+        self.inner_mut().and_then(Recursive::spare_link)
+    }
+
+    /// Returns `true` if `self` and `other` share their internal value, so that comparing
+    /// them for equality can be skipped. Returns `false` by default.
+    ///
+    /// If `Self == Arc`, this is `Arc::ptr_eq`.
+    fn ptr_eq(&self, other: &Self) -> bool {
+        let _ = other;
+        false
+    }
+}
+
+/// A container that [FlatDrop] can take apart.
+///
+/// This is implemented for every container of a [Recursive] type that converts into
+/// that type's [Recursive::Container]. Usually the two are the same, but the members
+/// of a [family!] of mutually recursive types share one container, so that they can
+/// all be taken apart by the same flat drop.
+pub trait FlatContainer: IntoOptionInner + Into<Self::Family> {
+    /// The container that the flat drop works with.
+    type Family: IntoOptionInner<Inner: Recursive<Container = Self::Family>>;
+}
+
+impl<K> FlatContainer for K
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive,
+    K: Into<<K::Inner as Recursive>::Container>,
+    <K::Inner as Recursive>::Container: IntoOptionInner,
+    <<K::Inner as Recursive>::Container as IntoOptionInner>::Inner:
+        Recursive<Container = <K::Inner as Recursive>::Container>,
+{
+    type Family = <K::Inner as Recursive>::Container;
 }
 
 /// If `K` is a container of a recursive type, such as `Box<T>` where `T: Recursive`,
@@ -96,38 +354,366 @@ pub trait IntoOptionInner {
 ///
 /// We keep the invariant that the inner object is always initialised, but will
 /// be dropped (exactly once) in the `drop` implementation.
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Default)]
 #[repr(transparent)]
 pub struct FlatDrop<K>(ManuallyDrop<K>)
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>;
+    K: FlatContainer;
 
 impl<K> Drop for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
 {
     fn drop(&mut self) {
         // Move out of the inner `ManuallyDrop`.
         // Safety: the inner value has not yet been dropped, and will not be used again.
-        let value = unsafe { ManuallyDrop::take(&mut self.0) };
-
-        // Construct a sequence of containers to drop.
-        let mut to_drop = vec![value];
+        let mut container = unsafe { ManuallyDrop::take(&mut self.0) }.into();
 
-        // Iteratively decompose each container from this list.
-        // This avoids creating excessive stack frames when destroying large objects.
-        while let Some(container) = to_drop.pop() {
-            if let Some(value) = container.into_option_inner() {
-                to_drop.extend(value.destruct());
+        // If another flat drop of the same type is in progress, let it do the work.
+        let reentrant = <K::Family as IntoOptionInner>::Inner::REENTRANT;
+        if let Some(reentrant) = reentrant {
+            match reentrant.defer(container) {
+                Ok(()) => return,
+                Err(value) => container = value,
             }
         }
 
+        drop_iterative(container, &mut ());
+
         // The drop glue will be a no-op since the field is `ManuallyDrop`.
     }
 }
 
+/// Drops `container` and everything inside it without recursion, calling `observer`
+/// on each node that is freed.
+fn drop_iterative<K>(mut container: K, observer: &mut impl DropObserver<K::Inner>) -> DropStats
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let traversal = K::Inner::TRAVERSAL;
+    match traversal {
+        Traversal::Lazy => return drop_lazy(container, observer),
+        Traversal::Threaded => return drop_threaded(container, observer),
+        _ => {}
+    }
+    let Some(reentrant) = K::Inner::REENTRANT else {
+        return drop_owned(container, observer);
+    };
+    let mut stats = DropStats::default();
+
+    // This is the loop of `drop_owned`, except that nested drops may push onto the
+    // worklist through the active pointer, so we only access it through `to_drop`,
+    // never holding a reference to it while running code that might drop a `FlatDrop`.
+    let worklist = UnsafeCell::new(Worklist::new());
+    let to_drop = worklist.get();
+    // Safety: the guard is dropped before `worklist`, and we keep to the rule above.
+    let _active = unsafe { reentrant.activate(NonNull::new_unchecked(to_drop), traversal) };
+    // Containers that didn't fit on the worklist because it couldn't grow.
+    let mut pending = None;
+
+    loop {
+        if let Some(mut value) = container.into_option_inner() {
+            stats.freed += 1;
+            observer.freed(&value);
+            value.before_destruct();
+            let mut parts = value.destruct();
+            if traversal == Traversal::DepthFirst && parts.size_hint().1 == Some(1) {
+                if let Some(next) = parts.next() {
+                    container = next;
+                    continue;
+                }
+            } else {
+                // Iterating over `parts` may drop things, so push one at a time.
+                for part in parts {
+                    // Safety: pushing doesn't run any code that could drop a `FlatDrop`.
+                    if let Err(part) = unsafe { (*to_drop).push(part, traversal) } {
+                        overflow(part, &mut pending, &mut stats, observer);
+                    }
+                }
+            }
+        } else {
+            stats.shared += 1;
+        }
+        // Safety: popping doesn't run any code that could drop a `FlatDrop`.
+        match unsafe { (*to_drop).pop(traversal) }.or_else(|| unlink(&mut pending)) {
+            Some(next) => container = next,
+            None => break,
+        }
+    }
+
+    stats
+}
+
+/// The loop of [drop_iterative] for types that aren't [Recursive::REENTRANT], so that
+/// nothing else can reach the worklist. Keeping the reentrancy handling out of it
+/// makes it as fast as a plain loop over a `Vec`.
+fn drop_owned<K>(mut container: K, observer: &mut impl DropObserver<K::Inner>) -> DropStats
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let traversal = K::Inner::TRAVERSAL;
+    let mut stats = DropStats::default();
+
+    // Construct a sequence of containers to drop.
+    // The container to decompose next is held separately from the worklist.
+    // If `destruct` returns an iterator that knows it has at most one item
+    // (such as `Option::into_iter`), we never need to touch the worklist at all,
+    // so dropping long chains of objects doesn't allocate. Skipping the worklist
+    // visits that item next, so this only applies to depth-first traversals.
+    let mut to_drop = Worklist::new();
+    // Containers that didn't fit on the worklist because it couldn't grow.
+    let mut pending = None;
+
+    // Iteratively decompose each container from this list.
+    // This avoids creating excessive stack frames when destroying large objects.
+    loop {
+        if let Some(mut value) = container.into_option_inner() {
+            stats.freed += 1;
+            observer.freed(&value);
+            value.before_destruct();
+            let mut parts = value.destruct();
+            if traversal == Traversal::DepthFirst && parts.size_hint().1 == Some(1) {
+                if let Some(next) = parts.next() {
+                    container = next;
+                    continue;
+                }
+            } else if let Err(part) = to_drop.extend(&mut parts, traversal) {
+                for part in core::iter::once(part).chain(parts) {
+                    overflow(part, &mut pending, &mut stats, observer);
+                }
+            }
+        } else {
+            stats.shared += 1;
+        }
+        match to_drop.pop(traversal).or_else(|| unlink(&mut pending)) {
+            Some(next) => container = next,
+            None => break,
+        }
+    }
+
+    stats
+}
+
+/// Drops `container` like [drop_iterative], but keeps the iterator returned by
+/// [Recursive::destruct] for each node on the current path, taking children from
+/// it only as they're needed. See [Traversal::Lazy].
+fn drop_lazy<K>(mut container: K, observer: &mut impl DropObserver<K::Inner>) -> DropStats
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let reentrant = K::Inner::REENTRANT;
+    let mut stats = DropStats::default();
+
+    // The worklist only holds containers deferred by nested drops,
+    // which we access under the same rules as in `drop_iterative`.
+    let worklist = UnsafeCell::new(Worklist::new());
+    let to_drop = worklist.get();
+    // Safety: the guard is dropped before `worklist`, and we keep to the rules.
+    let _active = reentrant.map(|reentrant| unsafe {
+        reentrant.activate(NonNull::new_unchecked(to_drop), Traversal::Lazy)
+    });
+    // Containers that didn't fit because `parts` couldn't grow.
+    let mut pending = None;
+
+    // The children left to visit of each node on the current path, innermost last.
+    let mut parts = Vec::new();
+    loop {
+        if let Some(mut value) = container.into_option_inner() {
+            stats.freed += 1;
+            observer.freed(&value);
+            value.before_destruct();
+            let children = value.destruct();
+            if parts.try_reserve(1).is_ok() {
+                parts.push(children);
+            } else {
+                for part in children {
+                    overflow(part, &mut pending, &mut stats, observer);
+                }
+            }
+        } else {
+            stats.shared += 1;
+        }
+
+        container = loop {
+            // Safety: popping doesn't run any code that could drop a `FlatDrop`.
+            if let Some(next) = unsafe { (*to_drop).pop(Traversal::Lazy) } {
+                break next;
+            }
+            let Some(children) = parts.last_mut() else {
+                match unlink(&mut pending) {
+                    Some(next) => break next,
+                    None => return stats,
+                }
+            };
+            match children.next() {
+                Some(next) => {
+                    // Discard finished iterators straight away, so that dropping
+                    // a long chain doesn't build up a long stack of them.
+                    if children.size_hint().1 == Some(0) {
+                        parts.pop();
+                    }
+                    break next;
+                }
+                None => {
+                    parts.pop();
+                }
+            }
+        };
+    }
+}
+
+/// Drops `container` like [drop_iterative], but links the containers waiting to be
+/// dropped together through their nodes' [Recursive::spare_link]s instead of
+/// collecting them in a worklist. See [Traversal::Threaded].
+fn drop_threaded<K>(container: K, observer: &mut impl DropObserver<K::Inner>) -> DropStats
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let reentrant = K::Inner::REENTRANT;
+    let traversal = Traversal::Threaded;
+    let mut stats = DropStats::default();
+
+    // The worklist only holds containers deferred by nested drops,
+    // which we access under the same rules as in `drop_iterative`.
+    let worklist = UnsafeCell::new(Worklist::new());
+    let to_drop = worklist.get();
+    // Safety: the guard is dropped before `worklist`, and we keep to the rules.
+    let _active = reentrant
+        .map(|reentrant| unsafe { reentrant.activate(NonNull::new_unchecked(to_drop), traversal) });
+
+    // The first of the linked containers. Each links to the next through its spare link.
+    let mut pending = Some(container);
+    // Values that have no spare link, but which we own anyway, because their container
+    // turned out not to lend out a spare link, or stopped being shared just as we released it.
+    // This is the only part of a threaded drop that may allocate.
+    let mut orphans = Vec::new();
+    loop {
+        let mut value = match orphans.pop() {
+            Some(value) => value,
+            None => {
+                // Safety: popping doesn't run any code that could drop a `FlatDrop`.
+                let Some(container) =
+                    unsafe { (*to_drop).pop(traversal) }.or_else(|| unlink(&mut pending))
+                else {
+                    break;
+                };
+                match container.into_option_inner() {
+                    Some(value) => value,
+                    None => {
+                        stats.shared += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+
+        stats.freed += 1;
+        observer.freed(&value);
+        value.before_destruct();
+        for part in value.destruct() {
+            let Err(part) = link(part, &mut pending) else {
+                continue;
+            };
+            match part.into_option_inner() {
+                Some(value) if orphans.try_reserve(1).is_ok() => orphans.push(value),
+                Some(value) => drop_now(value, &mut stats, observer),
+                None => stats.shared += 1,
+            }
+        }
+    }
+
+    stats
+}
+
+/// Links `container` onto the front of the list starting at `pending`, through its
+/// node's [Recursive::spare_link].
+/// Hands the container back if it isn't uniquely owned or has no empty spare link.
+fn link<K>(mut container: K, pending: &mut Option<K>) -> Result<(), K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    match container.inner_spare_link() {
+        Some(link) if link.is_none() => {
+            *link = pending.take();
+            *pending = Some(container);
+            Ok(())
+        }
+        _ => Err(container),
+    }
+}
+
+/// Takes the first container from the list starting at `pending`. See [link].
+fn unlink<K>(pending: &mut Option<K>) -> Option<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    let mut container = pending.take()?;
+    *pending = container.inner_spare_link().and_then(Option::take);
+    Some(container)
+}
+
+/// Deals with a container that there's no room for, because allocating failed.
+/// If possible, it's linked onto `pending` (see [link]) to be dropped later.
+/// Otherwise, it's released, and if that frees its contents, they're dropped right away.
+#[cold]
+fn overflow<K>(
+    container: K,
+    pending: &mut Option<K>,
+    stats: &mut DropStats,
+    observer: &mut impl DropObserver<K::Inner>,
+) where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    if let Err(container) = link(container, pending) {
+        match container.into_option_inner() {
+            Some(value) => drop_now(value, stats, observer),
+            None => stats.shared += 1,
+        }
+    }
+}
+
+/// Drops a node directly, as a last resort when there's no memory to take it apart.
+/// Each `FlatDrop` inside it starts its own flat drop, which uses a little more stack,
+/// and whose nodes aren't counted in `stats`.
+#[cold]
+fn drop_now<T>(mut value: T, stats: &mut DropStats, observer: &mut impl DropObserver<T>)
+where
+    T: Recursive,
+{
+    stats.freed += 1;
+    observer.freed(&value);
+    value.before_destruct();
+    drop(value);
+}
+
+/// Performs a single step of the iterative dropping procedure:
+/// takes the next container from `to_drop`, in the order given by [Recursive::TRAVERSAL],
+/// and replaces it with the containers of its recursive parts.
+/// Returns `false` if there was nothing left to drop.
+fn drop_step<K>(to_drop: &mut VecDeque<K>) -> bool
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    match K::Inner::TRAVERSAL.pop(to_drop) {
+        Some(container) => {
+            if let Some(mut value) = container.into_option_inner() {
+                value.before_destruct();
+                to_drop.extend(value.destruct());
+            }
+            true
+        }
+        None => false,
+    }
+}
+
 // Now that we've defined the core parts of the library, we'll make some API.
 
 impl<T> IntoOptionInner for Box<T> {
@@ -136,6 +722,10 @@ impl<T> IntoOptionInner for Box<T> {
     fn into_option_inner(self) -> Option<Self::Inner> {
         Some(*self)
     }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Some(self)
+    }
 }
 
 impl<T> IntoOptionInner for Rc<T> {
@@ -144,20 +734,36 @@ impl<T> IntoOptionInner for Rc<T> {
     fn into_option_inner(self) -> Option<Self::Inner> {
         Rc::into_inner(self)
     }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Rc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(self, other)
+    }
 }
 
+#[cfg(target_has_atomic = "ptr")]
 impl<T> IntoOptionInner for Arc<T> {
     type Inner = T;
 
     fn into_option_inner(self) -> Option<Self::Inner> {
         Arc::into_inner(self)
     }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Arc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(self, other)
+    }
 }
 
 impl<K> FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
 {
     pub const fn new(container: K) -> Self {
         Self(ManuallyDrop::new(container))
@@ -168,7 +774,7 @@ where
         // Once we take it, we need to be careful to not call `drop` on `self`.
         let value = unsafe { ManuallyDrop::take(&mut self.0) };
         // This doesn't leak, because `self` is contained purely on the stack.
-        std::mem::forget(self);
+        core::mem::forget(self);
         value
     }
 }
@@ -176,8 +782,7 @@ where
 impl<K, T> AsRef<T> for FlatDrop<K>
 where
     T: ?Sized,
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
     K: AsRef<T>,
 {
     fn as_ref(&self) -> &T {
@@ -188,8 +793,7 @@ where
 impl<K, T> AsMut<T> for FlatDrop<K>
 where
     T: ?Sized,
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
     K: AsMut<T>,
 {
     fn as_mut(&mut self) -> &mut T {
@@ -199,8 +803,7 @@ where
 
 impl<K> Deref for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
 {
     type Target = K;
 
@@ -211,8 +814,7 @@ where
 
 impl<K> DerefMut for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
 {
     fn deref_mut(&mut self) -> &mut K {
         self.0.deref_mut()
@@ -221,18 +823,16 @@ where
 
 impl<K> Display for FlatDrop<K>
 where
-    K: IntoOptionInner + Display,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer + Display,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         <K as Display>::fmt(self, f)
     }
 }
 
 impl<K> From<K> for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
+    K: FlatContainer,
 {
     fn from(value: K) -> Self {
         Self::new(value)
@@ -241,7 +841,8 @@ where
 
 impl<T> FlatDrop<Box<T>>
 where
-    T: Recursive<Container = Box<T>>,
+    T: Recursive,
+    Box<T>: FlatContainer,
 {
     pub fn new_boxed(value: T) -> Self {
         Self::new(Box::new(value))
@@ -250,16 +851,19 @@ where
 
 impl<T> FlatDrop<Rc<T>>
 where
-    T: Recursive<Container = Rc<T>>,
+    T: Recursive,
+    Rc<T>: FlatContainer,
 {
     pub fn new_rc(value: T) -> Self {
         Self::new(Rc::new(value))
     }
 }
 
+#[cfg(target_has_atomic = "ptr")]
 impl<T> FlatDrop<Arc<T>>
 where
-    T: Recursive<Container = Arc<T>>,
+    T: Recursive,
+    Arc<T>: FlatContainer,
 {
     pub fn new_arc(value: T) -> Self {
         Self::new(Arc::new(value))
@@ -269,9 +873,7 @@ where
 #[cfg(feature = "serde")]
 impl<K> serde::Serialize for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
-    K: serde::Serialize,
+    K: FlatContainer + serde::Serialize,
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -284,9 +886,7 @@ where
 #[cfg(feature = "serde")]
 impl<'de, K> serde::Deserialize<'de> for FlatDrop<K>
 where
-    K: IntoOptionInner,
-    K::Inner: Recursive<Container = K>,
-    K: serde::Deserialize<'de>,
+    K: FlatContainer + serde::Deserialize<'de>,
 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -298,10 +898,49 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::{FlatDrop, Recursive};
+    use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+    use core::{
+        cell::RefCell,
+        cmp::Ordering,
+        hash::{Hash, Hasher},
+        marker::PhantomData,
+    };
+    use std::println;
+    #[cfg(feature = "std")]
+    use std::{sync::mpsc::Sender, thread::ThreadId};
+
+    use crate::{
+        FlatDrop, Recursive, RecursiveClone, RecursiveDebug, RecursiveEq, RecursiveHash,
+        RecursiveOrd, RecursivePartialEq, RecursivePartialOrd, RecursiveRef, Traversal,
+    };
+
+    /// The stack size of the threads that tests build deep objects on.
+    pub(crate) const STACK_SIZE: usize = 4 * 1024;
+
+    /// Runs `f` on a new thread with a small stack, so that dropping anything
+    /// recursively in it overflows the stack.
+    pub(crate) fn on_small_stack(f: fn()) {
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    /// Reports the thread that it was dropped on.
+    #[cfg(feature = "std")]
+    pub(crate) struct ReportThread(pub(crate) Sender<ThreadId>);
+
+    #[cfg(feature = "std")]
+    impl Drop for ReportThread {
+        fn drop(&mut self) {
+            let _ = self.0.send(std::thread::current().id());
+        }
+    }
 
     /// Peano natural numbers.
-    enum Natural {
+    pub(crate) enum Natural {
         Zero,
         Succ(FlatDrop<Box<Natural>>),
     }
@@ -318,31 +957,152 @@ mod tests {
         }
     }
 
+    impl RecursiveRef for Natural {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            match self {
+                Natural::Zero => None,
+                Natural::Succ(pred) => Some(&**pred),
+            }
+            .into_iter()
+        }
+    }
+
+    impl RecursiveClone for Natural {
+        fn clone_with_children(&self, mut children: impl Iterator<Item = Box<Natural>>) -> Self {
+            match self {
+                Natural::Zero => Natural::Zero,
+                Natural::Succ(_) => Natural::Succ(FlatDrop::new(children.next().unwrap())),
+            }
+        }
+    }
+
+    impl RecursivePartialEq for Natural {
+        const REFLEXIVE: bool = true;
+
+        fn shallow_eq(&self, other: &Self) -> bool {
+            std::mem::discriminant(self) == std::mem::discriminant(other)
+        }
+    }
+
+    impl RecursiveEq for Natural {}
+
+    impl RecursivePartialOrd for Natural {
+        fn shallow_partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.shallow_cmp(other))
+        }
+    }
+
+    impl RecursiveOrd for Natural {
+        fn shallow_cmp(&self, other: &Self) -> Ordering {
+            // `Zero` sorts before `Succ`.
+            matches!(self, Natural::Succ(_)).cmp(&matches!(other, Natural::Succ(_)))
+        }
+    }
+
+    impl RecursiveHash for Natural {
+        fn shallow_hash<H: Hasher>(&self, state: &mut H) {
+            std::mem::discriminant(self).hash(state);
+        }
+    }
+
+    impl RecursiveDebug for Natural {
+        fn debug_name(&self) -> &str {
+            match self {
+                Natural::Zero => "Zero",
+                Natural::Succ(_) => "Succ",
+            }
+        }
+    }
+
     impl Natural {
         pub fn from_usize(value: usize) -> Self {
             (0..value).fold(Self::Zero, |nat, _| {
                 Self::Succ(FlatDrop::new(Box::new(nat)))
             })
         }
+
+        pub fn to_usize(&self) -> usize {
+            let mut value = 0;
+            let mut nat = self;
+            while let Natural::Succ(pred) = nat {
+                value += 1;
+                nat = pred;
+            }
+            value
+        }
     }
 
     #[test]
     fn test_large_natural() {
-        // Create a new thread with a 4kb stack and allocate a number far bigger than 4 * 1024.
-        const STACK_SIZE: usize = 4 * 1024;
-
-        fn task() {
+        on_small_stack(|| {
             let nat = Natural::from_usize(STACK_SIZE * 100);
             println!("Dropping...");
             drop(std::hint::black_box(nat));
             println!("Dropped.");
+        });
+    }
+
+    /// Gives a [Logged] tree its traversal.
+    pub(crate) trait Order {
+        const TRAVERSAL: Traversal;
+    }
+
+    /// Takes trees apart depth-first, the default.
+    pub(crate) struct DepthFirst;
+
+    impl Order for DepthFirst {
+        const TRAVERSAL: Traversal = Traversal::DepthFirst;
+    }
+
+    /// A tree that records the order in which its nodes are taken apart.
+    pub(crate) struct Logged<T: Order> {
+        id: u32,
+        log: Rc<RefCell<Vec<u32>>>,
+        children: Vec<FlatDrop<Box<Logged<T>>>>,
+        order: PhantomData<T>,
+    }
+
+    impl<T: Order> Recursive for Logged<T> {
+        type Container = Box<Logged<T>>;
+
+        const TRAVERSAL: Traversal = T::TRAVERSAL;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            self.children.into_iter().map(FlatDrop::into_inner)
         }
 
-        std::thread::Builder::new()
-            .stack_size(STACK_SIZE)
-            .spawn(task)
-            .unwrap()
-            .join()
-            .unwrap();
+        fn before_destruct(&mut self) {
+            self.log.borrow_mut().push(self.id);
+        }
+    }
+
+    impl<T: Order> Logged<T> {
+        /// Drops the tree that `build` makes from the node constructor it's given,
+        /// and returns the ids of its nodes in the order they were taken apart.
+        pub(crate) fn drop_order(
+            build: impl FnOnce(
+                &dyn Fn(u32, Vec<FlatDrop<Box<Self>>>) -> FlatDrop<Box<Self>>,
+            ) -> FlatDrop<Box<Self>>,
+        ) -> Vec<u32> {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let node = |id, children| {
+                FlatDrop::new_boxed(Logged {
+                    id,
+                    log: Rc::clone(&log),
+                    children,
+                    order: PhantomData,
+                })
+            };
+            drop(build(&node));
+            log.take()
+        }
+    }
+
+    #[test]
+    fn test_before_destruct_order() {
+        let order = Logged::<DepthFirst>::drop_order(|node| {
+            node(0, vec![node(1, vec![node(2, vec![])]), node(3, vec![])])
+        });
+        assert_eq!(order, [0, 3, 1, 2]);
     }
 }
@@ -0,0 +1,267 @@
+//! Flat drops of containers that hold any number of values.
+//!
+//! Children kept directly in a `Vec<T>` or `Box<[T]>` don't need a `Box` each, but
+//! such containers don't fit [IntoOptionInner]. [IntoInners] describes them instead,
+//! and [FlatDropMany] drops them. Internally, each container is wrapped up as a node
+//! whose children are the containers found in all of its values, so that the usual
+//! flat drop can take it apart.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{drop_iterative, IntoOptionInner, Recursive, Traversal};
+
+/// A trait for a container that holds any number of values.
+pub trait IntoInners {
+    type Inner;
+
+    /// Converts the container into the values that it owns.
+    /// This should never drop any of them.
+    fn into_inners(self) -> impl Iterator<Item = Self::Inner>;
+}
+
+impl<T> IntoInners for Vec<T> {
+    type Inner = T;
+
+    fn into_inners(self) -> impl Iterator<Item = Self::Inner> {
+        self.into_iter()
+    }
+}
+
+impl<T> IntoInners for Box<[T]> {
+    type Inner = T;
+
+    fn into_inners(self) -> impl Iterator<Item = Self::Inner> {
+        // This reuses the allocation, so it doesn't copy anything.
+        Vec::from(self).into_iter()
+    }
+}
+
+/// An optional single-value container, which owns nothing if it's `None`.
+impl<K> IntoInners for Option<K>
+where
+    K: IntoOptionInner,
+{
+    type Inner = K::Inner;
+
+    fn into_inners(self) -> impl Iterator<Item = Self::Inner> {
+        self.and_then(K::into_option_inner).into_iter()
+    }
+}
+
+/// Like [FlatDrop](crate::FlatDrop), but for a container holding any number of values,
+/// such as a `Vec` of children.
+///
+/// ```
+/// use flat_drop::{FlatDropMany, Recursive};
+///
+/// /// A rose tree, with no `Box` around each child.
+/// struct Node {
+///     children: FlatDropMany<Vec<Node>>,
+/// }
+///
+/// impl Recursive for Node {
+///     type Container = Vec<Node>;
+///
+///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+///         std::iter::once(self.children.into_inner())
+///     }
+/// }
+///
+/// let tree = (0..100_000).fold(Vec::new(), |children, _| {
+///     vec![Node {
+///         children: FlatDropMany::new(children),
+///     }]
+/// });
+/// drop(FlatDropMany::new(tree));
+/// ```
+///
+/// # Safety
+///
+/// As with `FlatDrop`, the inner object is always initialised,
+/// and is dropped (exactly once) in the `drop` implementation.
+#[derive(Default)]
+#[repr(transparent)]
+pub struct FlatDropMany<K>(ManuallyDrop<K>)
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>;
+
+impl<K> Drop for FlatDropMany<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    fn drop(&mut self) {
+        // Safety: the inner value has not yet been dropped, and will not be used again.
+        let container = unsafe { ManuallyDrop::take(&mut self.0) };
+        drop_iterative(Many(container), &mut ());
+    }
+}
+
+impl<K> FlatDropMany<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    pub const fn new(container: K) -> Self {
+        Self(ManuallyDrop::new(container))
+    }
+
+    pub fn into_inner(mut self) -> K {
+        // Safety: This value is always initialised, and `self` is forgotten below.
+        let value = unsafe { ManuallyDrop::take(&mut self.0) };
+        core::mem::forget(self);
+        value
+    }
+}
+
+impl<K> Deref for FlatDropMany<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    type Target = K;
+
+    fn deref(&self) -> &K {
+        self.0.deref()
+    }
+}
+
+impl<K> DerefMut for FlatDropMany<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    fn deref_mut(&mut self) -> &mut K {
+        self.0.deref_mut()
+    }
+}
+
+impl<K> From<K> for FlatDropMany<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    fn from(value: K) -> Self {
+        Self::new(value)
+    }
+}
+
+/// A container being dropped by [FlatDropMany], seen as a single-value container.
+struct Many<K>(K);
+
+/// The values of a container being dropped by [FlatDropMany], seen as a single node.
+struct Values<K>(K);
+
+impl<K> IntoOptionInner for Many<K> {
+    type Inner = Values<K>;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        Some(Values(self.0))
+    }
+}
+
+impl<K> Recursive for Values<K>
+where
+    K: IntoInners,
+    K::Inner: Recursive<Container = K>,
+{
+    type Container = Many<K>;
+
+    const TRAVERSAL: Traversal = K::Inner::TRAVERSAL;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        // Each value is taken apart, then dropped, as the flat drop reaches it.
+        self.0.into_inners().flat_map(|mut value| {
+            value.before_destruct();
+            value.destruct().map(Many)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    use super::FlatDropMany;
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        Recursive,
+    };
+
+    /// A rose tree storing its children directly.
+    struct Rose {
+        children: FlatDropMany<Vec<Rose>>,
+    }
+
+    impl Recursive for Rose {
+        type Container = Vec<Rose>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            core::iter::once(self.children.into_inner())
+        }
+    }
+
+    /// A binary tree storing its children in a boxed slice.
+    struct Binary {
+        children: FlatDropMany<Box<[Binary]>>,
+    }
+
+    impl Recursive for Binary {
+        type Container = Box<[Binary]>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            core::iter::once(self.children.into_inner())
+        }
+    }
+
+    /// A list whose tail is optional.
+    struct List {
+        next: FlatDropMany<Option<Box<List>>>,
+    }
+
+    impl Recursive for List {
+        type Container = Option<Box<List>>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            core::iter::once(self.next.into_inner())
+        }
+    }
+
+    #[test]
+    fn test_many_large_trees() {
+        on_small_stack(|| {
+            let leaf = || Rose {
+                children: FlatDropMany::new(Vec::new()),
+            };
+            let tree = (0..STACK_SIZE * 100).fold(vec![leaf()], |children, _| {
+                vec![
+                    Rose {
+                        children: FlatDropMany::new(children),
+                    },
+                    leaf(),
+                ]
+            });
+            drop(std::hint::black_box(FlatDropMany::new(tree)));
+
+            let leaf = || Binary {
+                children: FlatDropMany::new(Box::new([])),
+            };
+            let tree = (0..STACK_SIZE * 100).fold(leaf(), |tree, _| Binary {
+                children: FlatDropMany::new(Box::new([tree, leaf()])),
+            });
+            drop(std::hint::black_box(tree));
+
+            let list = (0..STACK_SIZE * 100).fold(None, |next, _| {
+                Some(Box::new(List {
+                    next: FlatDropMany::new(next),
+                }))
+            });
+            drop(std::hint::black_box(FlatDropMany::new(list)));
+        });
+    }
+}
@@ -0,0 +1,183 @@
+//! A container that may be either uniquely owned or shared.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::ops::Deref;
+
+use crate::{CloneContainer, IntoOptionInner};
+
+/// Either a [Box] or an [Arc], for recursive types that own some of their children
+/// and share others. Both kinds are taken apart by the same flat drop.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use flat_drop::{AnyContainer, FlatDrop, Recursive};
+///
+/// enum Term {
+///     Var(u32),
+///     App(FlatDrop<AnyContainer<Term>>, FlatDrop<AnyContainer<Term>>),
+/// }
+///
+/// impl Recursive for Term {
+///     type Container = AnyContainer<Term>;
+///
+///     fn destruct(self) -> impl Iterator<Item = Self::Container> {
+///         match self {
+///             Term::Var(_) => None,
+///             Term::App(f, x) => Some([f.into_inner(), x.into_inner()]),
+///         }
+///         .into_iter()
+///         .flatten()
+///     }
+/// }
+///
+/// let shared = Arc::new(Term::Var(0));
+/// let term = Term::App(
+///     FlatDrop::new(Box::new(Term::Var(1)).into()),
+///     FlatDrop::new(Arc::clone(&shared).into()),
+/// );
+/// drop(term);
+/// assert_eq!(Arc::strong_count(&shared), 1);
+/// ```
+#[derive(Debug)]
+pub enum AnyContainer<T> {
+    Box(Box<T>),
+    Arc(Arc<T>),
+}
+
+impl<T> IntoOptionInner for AnyContainer<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        match self {
+            AnyContainer::Box(boxed) => Some(*boxed),
+            AnyContainer::Arc(arc) => Arc::into_inner(arc),
+        }
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        match self {
+            AnyContainer::Box(boxed) => Some(boxed),
+            AnyContainer::Arc(arc) => Arc::get_mut(arc),
+        }
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (AnyContainer::Arc(a), AnyContainer::Arc(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Shared children stay shared, and owned children are cloned into new [Box]es.
+impl<T> CloneContainer for AnyContainer<T> {
+    fn try_share(&self) -> Option<Self> {
+        match self {
+            AnyContainer::Box(_) => None,
+            AnyContainer::Arc(arc) => Some(AnyContainer::Arc(Arc::clone(arc))),
+        }
+    }
+
+    fn from_inner(inner: T) -> Self {
+        AnyContainer::Box(Box::new(inner))
+    }
+}
+
+impl<T> Deref for AnyContainer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            AnyContainer::Box(boxed) => boxed,
+            AnyContainer::Arc(arc) => arc,
+        }
+    }
+}
+
+impl<T> From<Box<T>> for AnyContainer<T> {
+    fn from(boxed: Box<T>) -> Self {
+        AnyContainer::Box(boxed)
+    }
+}
+
+impl<T> From<Arc<T>> for AnyContainer<T> {
+    fn from(arc: Arc<T>) -> Self {
+        AnyContainer::Arc(arc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, sync::Arc};
+
+    use super::AnyContainer;
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        FlatDrop, Recursive, RecursiveClone, RecursiveRef,
+    };
+
+    /// A list whose tails may be owned or shared.
+    enum List {
+        Nil,
+        Cons(u32, FlatDrop<AnyContainer<List>>),
+    }
+
+    impl Recursive for List {
+        type Container = AnyContainer<List>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                List::Nil => None,
+                List::Cons(_, tail) => Some(tail.into_inner()),
+            }
+            .into_iter()
+        }
+    }
+
+    impl RecursiveRef for List {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            match self {
+                List::Nil => None,
+                List::Cons(_, tail) => Some(&**tail),
+            }
+            .into_iter()
+        }
+    }
+
+    impl RecursiveClone for List {
+        fn clone_with_children(&self, mut children: impl Iterator<Item = Self::Container>) -> Self {
+            match self {
+                List::Nil => List::Nil,
+                List::Cons(head, _) => List::Cons(*head, FlatDrop::new(children.next().unwrap())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixed_containers() {
+        on_small_stack(|| {
+            // Alternate between owned and shared tails, sharing the end of the list.
+            let shared = Arc::new(List::Nil);
+            let list =
+                (0..STACK_SIZE * 100).fold(AnyContainer::Arc(Arc::clone(&shared)), |tail, i| {
+                    let list = List::Cons(0, FlatDrop::new(tail));
+                    if i % 2 == 1 {
+                        AnyContainer::Box(Box::new(list))
+                    } else {
+                        AnyContainer::Arc(Arc::new(list))
+                    }
+                });
+            let list = FlatDrop::new(list);
+
+            // Cloning only copies the owned root, and shares the rest.
+            let clone = list.clone();
+            let stats = clone.drop_counted();
+            assert_eq!((stats.freed, stats.shared), (1, 1));
+
+            let stats = list.drop_counted();
+            assert_eq!((stats.freed, stats.shared), (STACK_SIZE * 100, 1));
+            assert_eq!(Arc::strong_count(&shared), 1);
+        });
+    }
+}
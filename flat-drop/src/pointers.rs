@@ -0,0 +1,398 @@
+//! Implementations of [IntoOptionInner] and [CloneContainer] for smart pointers
+//! from other crates, each behind a feature of the same name, and for the unstable
+//! `UniqueRc`, behind the `nightly` feature.
+
+use alloc::borrow::{Cow, ToOwned};
+#[cfg(feature = "servo_arc")]
+use core::{mem::ManuallyDrop, ptr};
+
+use crate::{CloneContainer, IntoOptionInner};
+
+/// Only owned data is ours to drop. Borrowed data is left alone.
+impl<B> IntoOptionInner for Cow<'_, B>
+where
+    B: ToOwned + ?Sized,
+{
+    type Inner = B::Owned;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        match self {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(owned) => Some(owned),
+        }
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        match self {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(owned) => Some(owned),
+        }
+    }
+}
+
+/// Cloning borrowed data just copies the reference.
+impl<B> CloneContainer for Cow<'_, B>
+where
+    B: Clone,
+{
+    fn try_share(&self) -> Option<Self> {
+        match self {
+            Cow::Borrowed(borrowed) => Some(Cow::Borrowed(borrowed)),
+            Cow::Owned(_) => None,
+        }
+    }
+
+    fn from_inner(inner: B) -> Self {
+        Cow::Owned(inner)
+    }
+}
+
+#[cfg(feature = "triomphe")]
+impl<T> IntoOptionInner for triomphe::Arc<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        triomphe::Arc::into_unique(self).map(triomphe::UniqueArc::into_inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        triomphe::Arc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        triomphe::Arc::ptr_eq(self, other)
+    }
+}
+
+#[cfg(feature = "triomphe")]
+impl<T> CloneContainer for triomphe::Arc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(triomphe::Arc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        triomphe::Arc::new(inner)
+    }
+}
+
+#[cfg(feature = "triomphe")]
+impl<T> IntoOptionInner for triomphe::UniqueArc<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        Some(triomphe::UniqueArc::into_inner(self))
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "triomphe")]
+impl<T> CloneContainer for triomphe::UniqueArc<T> {
+    fn try_share(&self) -> Option<Self> {
+        None
+    }
+
+    fn from_inner(inner: T) -> Self {
+        triomphe::UniqueArc::new(inner)
+    }
+}
+
+/// `servo_arc` has no equivalent of `Arc::into_inner`, so we check whether the `Arc` is
+/// unique, and if it isn't, drop our reference.
+/// If another thread drops the last other reference at the same time, neither thread
+/// sees a unique `Arc`, and the contents are dropped normally. Each `FlatDrop` inside
+/// them still drops iteratively, so this costs at most one extra stack frame.
+#[cfg(feature = "servo_arc")]
+impl<T> IntoOptionInner for servo_arc::Arc<T> {
+    type Inner = T;
+
+    fn into_option_inner(mut self) -> Option<Self::Inner> {
+        let inner = servo_arc::Arc::get_mut(&mut self)?;
+        // Safety: the `Arc` is unique, so nobody else can observe its contents.
+        // We free the allocation below without dropping them again.
+        let inner = unsafe { ptr::read(inner) };
+        let raw = servo_arc::Arc::into_raw(self);
+        // Safety: `ManuallyDrop<T>` has the same layout as `T`, so the pointer is to the
+        // contents of an `Arc<ManuallyDrop<T>>`. Dropping that frees the allocation.
+        drop(unsafe { servo_arc::Arc::from_raw(raw.cast::<ManuallyDrop<T>>()) });
+        Some(inner)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        servo_arc::Arc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        servo_arc::Arc::ptr_eq(self, other)
+    }
+}
+
+#[cfg(feature = "servo_arc")]
+impl<T> CloneContainer for servo_arc::Arc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(servo_arc::Arc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        servo_arc::Arc::new(inner)
+    }
+}
+
+#[cfg(feature = "rclite")]
+impl<T> IntoOptionInner for rclite::Rc<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        rclite::Rc::into_inner(self)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        rclite::Rc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        rclite::Rc::ptr_eq(self, other)
+    }
+}
+
+#[cfg(feature = "rclite")]
+impl<T> CloneContainer for rclite::Rc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(rclite::Rc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        rclite::Rc::new(inner)
+    }
+}
+
+#[cfg(feature = "rclite")]
+impl<T> IntoOptionInner for rclite::Arc<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        rclite::Arc::into_inner(self)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        rclite::Arc::get_mut(self)
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        rclite::Arc::ptr_eq(self, other)
+    }
+}
+
+#[cfg(feature = "rclite")]
+impl<T> CloneContainer for rclite::Arc<T> {
+    fn try_share(&self) -> Option<Self> {
+        Some(rclite::Arc::clone(self))
+    }
+
+    fn from_inner(inner: T) -> Self {
+        rclite::Arc::new(inner)
+    }
+}
+
+/// A `UniqueRc` has no other strong references, so its contents are always ours.
+#[cfg(feature = "nightly")]
+impl<T> IntoOptionInner for alloc::rc::UniqueRc<T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        alloc::rc::Rc::into_inner(alloc::rc::UniqueRc::into_rc(self))
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T> CloneContainer for alloc::rc::UniqueRc<T> {
+    fn try_share(&self) -> Option<Self> {
+        None
+    }
+
+    fn from_inner(inner: T) -> Self {
+        alloc::rc::UniqueRc::new(inner)
+    }
+}
+
+/// The contents are moved out of the arena, and their memory is reclaimed when the arena is.
+#[cfg(feature = "bumpalo")]
+impl<T> IntoOptionInner for bumpalo::boxed::Box<'_, T> {
+    type Inner = T;
+
+    fn into_option_inner(self) -> Option<Self::Inner> {
+        Some(bumpalo::boxed::Box::into_inner(self))
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut Self::Inner> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+// `Cow<[T]>` derefs to a slice rather than the `Vec` it owns, so it isn't a `CloneContainer`.
+#[allow(clippy::owned_cow)]
+mod tests {
+    use alloc::{borrow::Cow, boxed::Box, vec, vec::Vec};
+
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        FlatDrop, Recursive, RecursiveClone, RecursiveRef,
+    };
+
+    /// A tree whose children may be borrowed from another tree.
+    #[derive(Clone)]
+    struct Node<'a> {
+        children: FlatDrop<Cow<'a, Vec<Node<'a>>>>,
+    }
+
+    impl<'a> Recursive for Vec<Node<'a>> {
+        type Container = Cow<'a, Vec<Node<'a>>>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            self.into_iter().map(|node| node.children.into_inner())
+        }
+    }
+
+    impl<'a> RecursiveRef for Vec<Node<'a>> {
+        fn children(&self) -> impl Iterator<Item = &Self::Container> {
+            self.iter().map(|node| &*node.children)
+        }
+    }
+
+    impl<'a> RecursiveClone for Vec<Node<'a>> {
+        fn clone_with_children(&self, children: impl Iterator<Item = Self::Container>) -> Self {
+            children.map(node).collect()
+        }
+    }
+
+    fn node<'a>(children: Cow<'a, Vec<Node<'a>>>) -> Node<'a> {
+        Node {
+            children: FlatDrop::new(children),
+        }
+    }
+
+    #[test]
+    fn test_cow() {
+        on_small_stack(|| {
+            // `Node` is invariant in its lifetime, so the shared children must outlive it.
+            let shared = Box::leak(Box::new(
+                (0..STACK_SIZE * 100)
+                    .fold(Vec::new(), |children, _| vec![node(Cow::Owned(children))]),
+            ));
+            let tree = (0..STACK_SIZE * 100)
+                .fold(vec![node(Cow::Borrowed(&*shared))], |children, _| {
+                    vec![node(Cow::Owned(children))]
+                });
+            let clone = node(Cow::Owned(tree)).clone();
+            drop(std::hint::black_box(clone));
+            // The borrowed children are untouched.
+            assert_eq!(shared.len(), 1);
+        });
+    }
+
+    /// Peano natural numbers, generic over the container.
+    #[cfg(any(
+        feature = "triomphe",
+        feature = "servo_arc",
+        feature = "rclite",
+        feature = "nightly"
+    ))]
+    macro_rules! test_natural {
+        ($name:ident, $container:ty, $new:expr) => {
+            #[test]
+            fn $name() {
+                enum Natural {
+                    Zero,
+                    Succ(FlatDrop<$container>),
+                }
+
+                impl Recursive for Natural {
+                    type Container = $container;
+
+                    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+                        match self {
+                            Natural::Zero => None,
+                            Natural::Succ(pred) => Some(pred.into_inner()),
+                        }
+                        .into_iter()
+                    }
+                }
+
+                on_small_stack(|| {
+                    let nat = (0..STACK_SIZE * 100).fold(Natural::Zero, |nat, _| {
+                        Natural::Succ(FlatDrop::new($new(nat)))
+                    });
+                    drop(std::hint::black_box(nat));
+                });
+            }
+        };
+    }
+
+    #[cfg(feature = "triomphe")]
+    test_natural!(
+        test_triomphe_arc,
+        triomphe::Arc<Natural>,
+        triomphe::Arc::new
+    );
+
+    #[cfg(feature = "triomphe")]
+    test_natural!(
+        test_triomphe_unique_arc,
+        triomphe::UniqueArc<Natural>,
+        triomphe::UniqueArc::new
+    );
+
+    #[cfg(feature = "servo_arc")]
+    test_natural!(test_servo_arc, servo_arc::Arc<Natural>, servo_arc::Arc::new);
+
+    #[cfg(feature = "rclite")]
+    test_natural!(test_rclite_rc, rclite::Rc<Natural>, rclite::Rc::new);
+
+    #[cfg(feature = "rclite")]
+    test_natural!(test_rclite_arc, rclite::Arc<Natural>, rclite::Arc::new);
+
+    #[cfg(feature = "nightly")]
+    test_natural!(
+        test_unique_rc,
+        alloc::rc::UniqueRc<Natural>,
+        alloc::rc::UniqueRc::new
+    );
+
+    #[cfg(feature = "bumpalo")]
+    #[test]
+    fn test_bumpalo_box() {
+        use bumpalo::{boxed::Box, Bump};
+
+        enum Natural<'a> {
+            Zero,
+            Succ(FlatDrop<Box<'a, Natural<'a>>>),
+        }
+
+        impl<'a> Recursive for Natural<'a> {
+            type Container = Box<'a, Natural<'a>>;
+
+            fn destruct(self) -> impl Iterator<Item = Self::Container> {
+                match self {
+                    Natural::Zero => None,
+                    Natural::Succ(pred) => Some(pred.into_inner()),
+                }
+                .into_iter()
+            }
+        }
+
+        on_small_stack(|| {
+            let bump = Bump::new();
+            let nat = (0..STACK_SIZE * 100).fold(Natural::Zero, |nat, _| {
+                Natural::Succ(FlatDrop::new(Box::new_in(nat, &bump)))
+            });
+            drop(std::hint::black_box(nat));
+        });
+    }
+}
@@ -0,0 +1,141 @@
+//! Dropping recursive objects incrementally, a bounded amount of work at a time.
+
+use alloc::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::{drop_step, FlatDrop, IntoOptionInner, Recursive};
+
+/// A queue of containers to be dropped incrementally.
+///
+/// Where dropping a [FlatDrop] destroys the whole object at once, a `DropQueue`
+/// lets the caller decide how much work to do at a time using [DropQueue::step]
+/// or [DropQueue::step_for]. This is useful for spreading the destruction of a
+/// large object over several frames of a game loop, for example.
+///
+/// Anything left in the queue when it is dropped is destroyed immediately.
+pub struct DropQueue<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    to_drop: VecDeque<K>,
+}
+
+impl<K> DropQueue<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    /// How many containers [DropQueue::step_for] drops between checks of the clock.
+    #[cfg(feature = "std")]
+    const STEPS_PER_CLOCK_CHECK: usize = 64;
+
+    pub const fn new() -> Self {
+        Self {
+            to_drop: VecDeque::new(),
+        }
+    }
+
+    /// Adds a container to the queue. It will not be dropped until the queue is stepped.
+    pub fn push(&mut self, container: K) {
+        self.to_drop.push_back(container);
+    }
+
+    /// Adds the contents of a [FlatDrop] to the queue.
+    pub fn push_flat(&mut self, value: FlatDrop<K>) {
+        self.push(value.into_inner());
+    }
+
+    /// Returns `true` if there is no more work to do.
+    pub fn is_empty(&self) -> bool {
+        self.to_drop.is_empty()
+    }
+
+    /// Drops at most `max_nodes` containers.
+    /// Returns `true` if there is still work left to do.
+    pub fn step(&mut self, max_nodes: usize) -> bool {
+        for _ in 0..max_nodes {
+            if !drop_step(&mut self.to_drop) {
+                break;
+            }
+        }
+        !self.is_empty()
+    }
+
+    /// Drops containers until either the queue is empty or `budget` has elapsed.
+    /// Returns `true` if there is still work left to do.
+    ///
+    /// The clock is only checked periodically, so this may slightly overrun its budget.
+    #[cfg(feature = "std")]
+    pub fn step_for(&mut self, budget: Duration) -> bool {
+        let start = Instant::now();
+        while self.step(Self::STEPS_PER_CLOCK_CHECK) {
+            if start.elapsed() >= budget {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<K> Default for DropQueue<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Extend<K> for DropQueue<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.to_drop.extend(iter);
+    }
+}
+
+impl<K> Drop for DropQueue<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    fn drop(&mut self) {
+        while drop_step(&mut self.to_drop) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    #[cfg(feature = "std")]
+    use std::time::Duration;
+
+    use crate::{tests::Natural, DropQueue};
+
+    #[test]
+    fn test_step() {
+        let mut queue = DropQueue::new();
+        queue.push(Box::new(Natural::from_usize(999)));
+
+        // There are 1000 containers in total, including the one for zero.
+        for _ in 0..99 {
+            assert!(queue.step(10));
+        }
+        assert!(!queue.step(10));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_step_for() {
+        let mut queue = DropQueue::new();
+        queue.push(Box::new(Natural::from_usize(100_000)));
+        while queue.step_for(Duration::from_micros(100)) {}
+        assert!(queue.is_empty());
+    }
+}
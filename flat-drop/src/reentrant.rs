@@ -0,0 +1,185 @@
+//! Flattening drops that begin while another flat drop is already in progress.
+//!
+//! If [Recursive::destruct](crate::Recursive::destruct) leaves some `FlatDrop` fields
+//! inside the value it returns, dropping that value drops those fields, each of which
+//! would ordinarily start its own iterative drop. For types that opt in with
+//! [Recursive::REENTRANT](crate::Recursive::REENTRANT), the outermost flat drop on each
+//! thread registers its worklist here, and nested drops of the same container type
+//! push their containers onto it instead.
+//!
+//! This needs a thread-local to find the active worklist, so without the `std` feature,
+//! nested drops always start their own iterative drop.
+
+use core::{any::TypeId, fmt, marker::PhantomData, ptr::NonNull};
+#[cfg(feature = "std")]
+use std::cell::Cell;
+
+use crate::{worklist::Worklist, Traversal};
+
+/// The type of container held in a worklist, a pointer to that worklist,
+/// and the order in which it is traversed.
+#[cfg(feature = "std")]
+type Active = (TypeId, NonNull<()>, Traversal);
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// The worklist of the innermost reentrant flat drop in progress on this thread.
+    static ACTIVE: Cell<Option<Active>> = const { Cell::new(None) };
+}
+
+/// Allows nested drops of `FlatDrop<K>` to join a flat drop already in progress.
+/// See [Recursive::REENTRANT](crate::Recursive::REENTRANT).
+///
+/// A nested drop may be deferred until after the code that triggered it has returned,
+/// which is only sound if the container can't borrow anything, so this can only be
+/// constructed when `K: 'static`.
+pub struct Reentrant<K> {
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    type_id: fn() -> TypeId,
+    _marker: PhantomData<fn(K) -> K>,
+}
+
+impl<K> Reentrant<K>
+where
+    K: 'static,
+{
+    pub const fn new() -> Self {
+        Self {
+            type_id: TypeId::of::<K>,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K> Default for Reentrant<K>
+where
+    K: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> Reentrant<K> {
+    /// If a reentrant flat drop of containers of type `K` is in progress on this
+    /// thread, pushes `container` onto its worklist.
+    /// Otherwise, or if the worklist couldn't grow, hands the container back.
+    #[cfg(feature = "std")]
+    pub(crate) fn defer(self, container: K) -> Result<(), K> {
+        let Ok(Some((type_id, worklist, traversal))) = ACTIVE.try_with(Cell::get) else {
+            return Err(container);
+        };
+        if type_id != (self.type_id)() {
+            return Err(container);
+        }
+        // Safety: `K: 'static` because we were constructed, so the type IDs matching
+        // means that the active worklist really does hold containers of type `K`.
+        // It is still alive because it is deregistered before it is dropped.
+        // The flat drop that owns it never holds a reference to it while running code
+        // that could drop a `FlatDrop`, so we have exclusive access here.
+        unsafe { (*worklist.cast::<Worklist<K>>().as_ptr()).push(container, traversal) }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn defer(self, container: K) -> Result<(), K> {
+        Err(container)
+    }
+
+    /// Registers `worklist`, which is traversed in the order `traversal`, as the
+    /// active worklist on this thread until the returned guard is dropped.
+    ///
+    /// # Safety
+    ///
+    /// The worklist must outlive the guard, and the caller must not hold a reference
+    /// to it while running any code that could drop a `FlatDrop<K>`.
+    #[cfg(feature = "std")]
+    pub(crate) unsafe fn activate(
+        self,
+        worklist: NonNull<Worklist<K>>,
+        traversal: Traversal,
+    ) -> ActiveGuard {
+        let active = ((self.type_id)(), worklist.cast(), traversal);
+        ActiveGuard {
+            previous: ACTIVE.try_with(|cell| cell.replace(Some(active))).ok(),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) unsafe fn activate(
+        self,
+        _worklist: NonNull<Worklist<K>>,
+        _traversal: Traversal,
+    ) -> ActiveGuard {
+        ActiveGuard {}
+    }
+}
+
+impl<K> Clone for Reentrant<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for Reentrant<K> {}
+
+impl<K> fmt::Debug for Reentrant<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reentrant").finish_non_exhaustive()
+    }
+}
+
+/// Restores the previously active worklist when dropped.
+pub(crate) struct ActiveGuard {
+    /// `None` if the worklist could not be registered because the thread is shutting down.
+    #[cfg(feature = "std")]
+    previous: Option<Option<Active>>,
+}
+
+#[cfg(feature = "std")]
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            let _ = ACTIVE.try_with(|cell| cell.set(previous));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{
+        tests::{on_small_stack, STACK_SIZE},
+        FlatDrop, Recursive, Reentrant,
+    };
+
+    /// A binary tree whose `destruct` only extracts the left child,
+    /// leaving the right child to be dropped as a field.
+    enum Tree {
+        Leaf,
+        Node(FlatDrop<Box<Tree>>, #[allow(dead_code)] FlatDrop<Box<Tree>>),
+    }
+
+    impl Recursive for Tree {
+        type Container = Box<Tree>;
+
+        const REENTRANT: Option<Reentrant<Self::Container>> = Some(Reentrant::new());
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Tree::Leaf => None,
+                Tree::Node(left, _) => Some(left.into_inner()),
+            }
+            .into_iter()
+        }
+    }
+
+    #[test]
+    fn test_nested_drops_are_flattened() {
+        on_small_stack(|| {
+            // A tree leaning to the right, which `destruct` never reaches directly.
+            let tree = (0..STACK_SIZE * 100).fold(Tree::Leaf, |tree, _| {
+                Tree::Node(FlatDrop::new_boxed(Tree::Leaf), FlatDrop::new_boxed(tree))
+            });
+            drop(std::hint::black_box(FlatDrop::new_boxed(tree)));
+        });
+    }
+}
@@ -0,0 +1,120 @@
+//! Observing what a flat drop frees.
+//!
+//! A container whose [IntoOptionInner::into_option_inner] returns `None`, such as an
+//! [Rc](alloc::rc::Rc) with other owners, is left alone by a flat drop, so only its
+//! reference count is decremented. For hash-consed or interned data, most of an
+//! object may be shared like this, so it is useful to know how much was actually freed.
+
+use crate::{drop_iterative, FlatDrop, IntoOptionInner, Recursive};
+
+/// Counts of the containers visited by a flat drop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DropStats {
+    /// The containers whose contents were dropped.
+    pub freed: usize,
+    /// The containers that were shared with other owners, so were only decremented.
+    pub shared: usize,
+}
+
+/// A callback invoked for each node freed by [FlatDrop::drop_with].
+pub trait DropObserver<T> {
+    /// Called with each node that is about to be taken apart and dropped.
+    fn freed(&mut self, node: &T);
+}
+
+impl<T> DropObserver<T> for () {
+    fn freed(&mut self, _node: &T) {}
+}
+
+impl<T, F> DropObserver<T> for F
+where
+    F: FnMut(&T),
+{
+    fn freed(&mut self, node: &T) {
+        self(node)
+    }
+}
+
+impl<K> FlatDrop<K>
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>,
+{
+    /// Drops this object, calling `observer` on each node that is freed, and returns
+    /// how many containers were freed and how many were shared.
+    ///
+    /// Unlike an ordinary drop, this never joins a flat drop that is already in
+    /// progress (see [Recursive::REENTRANT]), so everything inside is counted.
+    pub fn drop_with(self, mut observer: impl DropObserver<K::Inner>) -> DropStats {
+        drop_iterative(self.into_inner(), &mut observer)
+    }
+
+    /// Drops this object and returns how many containers were freed and how many
+    /// were shared.
+    pub fn drop_counted(self) -> DropStats {
+        self.drop_with(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        DropStats, FlatDrop, Recursive,
+    };
+
+    /// A hash-consed term, which may share its arguments with other terms.
+    enum Term {
+        Var(u32),
+        App(FlatDrop<Rc<Term>>, FlatDrop<Rc<Term>>),
+    }
+
+    impl Recursive for Term {
+        type Container = Rc<Term>;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            match self {
+                Term::Var(_) => None,
+                Term::App(f, x) => Some([f.into_inner(), x.into_inner()]),
+            }
+            .into_iter()
+            .flatten()
+        }
+    }
+
+    #[test]
+    fn test_drop_with_shared() {
+        let x = Rc::new(Term::Var(0));
+        let y = FlatDrop::new_rc(Term::Var(1));
+        let f = FlatDrop::new_rc(Term::App(FlatDrop::new(Rc::clone(&x)), y));
+        let term = FlatDrop::new_rc(Term::App(f, FlatDrop::new(Rc::clone(&x))));
+
+        let mut vars = Vec::new();
+        let stats = term.drop_with(|term: &Term| {
+            if let Term::Var(var) = term {
+                vars.push(*var);
+            }
+        });
+        assert_eq!(
+            stats,
+            DropStats {
+                freed: 3,
+                shared: 2
+            }
+        );
+        assert_eq!(vars, [1]);
+        assert_eq!(Rc::strong_count(&x), 1);
+    }
+
+    #[test]
+    fn test_drop_counted_large_natural() {
+        on_small_stack(|| {
+            let nat = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let stats = nat.drop_counted();
+            assert_eq!(stats.freed, STACK_SIZE * 100 + 1);
+            assert_eq!(stats.shared, 0);
+        });
+    }
+}
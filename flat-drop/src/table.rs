@@ -0,0 +1,249 @@
+//! Serializing recursive objects as flat tables of nodes.
+//!
+//! Forwarding `Serialize` to the container serializes a recursive object recursively,
+//! which overflows the stack for deep objects. Instead, [FlatSerialize] writes the object
+//! out as a sequence of nodes in post-order. Each node is written as a pair of its
+//! shallow part and its number of children, which are the entries immediately before
+//! it once their own descendants are accounted for. The root is the last entry.
+//! [FlatDeserialize] reads this format back, rebuilding the object with an explicit stack.
+
+use alloc::{vec, vec::Vec};
+use core::{fmt, marker::PhantomData, ops::Deref};
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{FlatDrop, IntoOptionInner, Recursive, RecursiveRef};
+
+/// A recursive type whose nodes can be serialized one at a time.
+pub trait RecursiveSerialize: RecursiveRef {
+    /// Serializes the parts of this node that aren't children.
+    fn serialize_shallow<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+}
+
+/// A recursive type whose nodes can be deserialized one at a time.
+pub trait RecursiveDeserialize<'de>: Recursive + Sized {
+    /// The parts of a node that aren't children.
+    type Shallow: Deserialize<'de>;
+
+    /// Builds a node from its shallow part and its children, in order.
+    /// Returns `None` if the node can't have that many children.
+    fn from_parts(
+        shallow: Self::Shallow,
+        children: impl ExactSizeIterator<Item = Self::Container>,
+    ) -> Option<Self>;
+}
+
+/// Serializes a [FlatDrop] as a flat table of nodes, without recursion.
+/// See the [module documentation](self) for the format.
+pub struct FlatSerialize<'a, K>(pub &'a FlatDrop<K>)
+where
+    K: IntoOptionInner,
+    K::Inner: RecursiveSerialize<Container = K>;
+
+/// Deserializes a [FlatDrop] from a flat table of nodes, without recursion.
+/// See the [module documentation](self) for the format.
+pub struct FlatDeserialize<K>(pub FlatDrop<K>)
+where
+    K: IntoOptionInner,
+    K::Inner: Recursive<Container = K>;
+
+/// Iterates over the nodes of `root` in post-order, together with their numbers of children.
+fn post_order<K>(root: &K) -> impl Iterator<Item = (&K::Inner, usize)>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveRef<Container = K>,
+{
+    // The nodes whose children we're partway through, and how many we've visited so far.
+    let mut stack = vec![(&**root, root.children(), 0)];
+    core::iter::from_fn(move || loop {
+        let (_, children, count) = stack.last_mut()?;
+        match children.next() {
+            Some(child) => {
+                *count += 1;
+                stack.push((&**child, child.children(), 0));
+            }
+            None => {
+                let (node, _, count) = stack.pop()?;
+                return Some((node, count));
+            }
+        }
+    })
+}
+
+/// A single entry of the node table.
+struct Entry<'a, T>(&'a T, usize);
+
+/// The shallow part of a node.
+struct Shallow<'a, T>(&'a T);
+
+impl<T> Serialize for Entry<'_, T>
+where
+    T: RecursiveSerialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&Shallow(self.0))?;
+        tuple.serialize_element(&self.1)?;
+        tuple.end()
+    }
+}
+
+impl<T> Serialize for Shallow<'_, T>
+where
+    T: RecursiveSerialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize_shallow(serializer)
+    }
+}
+
+impl<K> Serialize for FlatSerialize<'_, K>
+where
+    K: IntoOptionInner + Deref<Target = K::Inner>,
+    K::Inner: RecursiveSerialize<Container = K>,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(post_order(&**self.0).map(|(node, count)| Entry(node, count)))
+    }
+}
+
+impl<'de, K> Deserialize<'de> for FlatDeserialize<K>
+where
+    K: IntoOptionInner + From<K::Inner>,
+    K::Inner: RecursiveDeserialize<'de, Container = K>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_seq(TableVisitor(PhantomData))
+            .map(|root| Self(FlatDrop::new(root)))
+    }
+}
+
+/// Rebuilds an object from its node table.
+struct TableVisitor<K>(PhantomData<fn() -> K>);
+
+impl<'de, K> Visitor<'de> for TableVisitor<K>
+where
+    K: IntoOptionInner + From<K::Inner>,
+    K::Inner: RecursiveDeserialize<'de, Container = K>,
+{
+    type Value = K;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table of nodes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<K, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // The containers that have been built but don't yet have a parent.
+        let mut built = Vec::new();
+        while let Some((shallow, count)) = seq.next_element()? {
+            let Some(start) = built.len().checked_sub(count) else {
+                return Err(de::Error::custom(format_args!(
+                    "node has {count} children, but only {} are available",
+                    built.len()
+                )));
+            };
+            let node = K::Inner::from_parts(shallow, built.drain(start..)).ok_or_else(|| {
+                de::Error::custom(format_args!("invalid node with {count} children"))
+            })?;
+            built.push(K::from(node));
+        }
+
+        let root = built.pop();
+        match (root, built.is_empty()) {
+            (Some(root), true) => Ok(root),
+            (None, _) => Err(de::Error::invalid_length(0, &self)),
+            (Some(_), false) => Err(de::Error::custom("node table has more than one root")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, string::String};
+
+    use serde::Serializer;
+
+    use crate::{
+        tests::{on_small_stack, Natural, STACK_SIZE},
+        FlatDeserialize, FlatDrop, FlatSerialize, RecursiveDeserialize, RecursiveSerialize,
+    };
+
+    impl RecursiveSerialize for Natural {
+        fn serialize_shallow<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                Natural::Zero => serializer.serialize_unit_variant("Natural", 0, "Zero"),
+                Natural::Succ(_) => serializer.serialize_unit_variant("Natural", 1, "Succ"),
+            }
+        }
+    }
+
+    impl RecursiveDeserialize<'_> for Natural {
+        type Shallow = String;
+
+        fn from_parts(
+            shallow: String,
+            mut children: impl ExactSizeIterator<Item = Box<Natural>>,
+        ) -> Option<Self> {
+            match (shallow.as_str(), children.next()) {
+                ("Zero", None) => Some(Natural::Zero),
+                ("Succ", Some(pred)) if children.len() == 0 => {
+                    Some(Natural::Succ(FlatDrop::new(pred)))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize_natural() {
+        let nat = FlatDrop::new_boxed(Natural::from_usize(2));
+        assert_eq!(
+            serde_json::to_string(&FlatSerialize(&nat)).unwrap(),
+            r#"[["Zero",0],["Succ",1],["Succ",1]]"#
+        );
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        for json in [
+            r#"[]"#,
+            r#"[["Succ",1]]"#,
+            r#"[["Zero",0],["Zero",1]]"#,
+            r#"[["Zero",0],["Zero",0]]"#,
+        ] {
+            assert!(serde_json::from_str::<FlatDeserialize<Box<Natural>>>(json).is_err());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_large_natural() {
+        on_small_stack(|| {
+            let nat = FlatDrop::new_boxed(Natural::from_usize(STACK_SIZE * 100));
+            let json = serde_json::to_vec(&FlatSerialize(&nat)).unwrap();
+            assert!(json.ends_with(br#"["Succ",1]]"#));
+
+            let FlatDeserialize(round_trip) = serde_json::from_slice(&json).unwrap();
+            assert!(nat == round_trip);
+        });
+    }
+}
@@ -0,0 +1,217 @@
+//! The order in which a flat drop visits the nodes of an object.
+
+use alloc::collections::VecDeque;
+
+/// The order in which a flat drop takes apart the nodes of an object.
+/// See [Recursive::TRAVERSAL](crate::Recursive::TRAVERSAL).
+///
+/// Every node is still taken apart before its children, whatever the order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Traversal {
+    /// Visit the most recently found node first, using the worklist as a stack.
+    /// Children are visited last to first. The worklist holds at most the
+    /// unvisited siblings of each node on the current path, which is small
+    /// for deep, narrow objects.
+    #[default]
+    DepthFirst,
+    /// Visit the nodes level by level, using the worklist as a queue.
+    /// Children are visited first to last. The worklist holds at most about
+    /// two levels of the object, which is small for wide, shallow objects,
+    /// and siblings are visited together.
+    BreadthFirst,
+    /// Visit the nodes breadth-first while the worklist holds at most `max_width`
+    /// containers, and depth-first otherwise. This limits how far the worklist grows
+    /// from visiting wide objects breadth-first, but doesn't bound its size: the
+    /// children of each node on the current path are still collected on it.
+    /// Use [Traversal::Lazy] to bound the extra memory by the depth of the object.
+    Hybrid { max_width: usize },
+    /// Visit the nodes depth-first, but instead of collecting the children of each
+    /// node on the worklist, keep the iterator returned by
+    /// [Recursive::destruct](crate::Recursive::destruct) and take one child from it
+    /// at a time. Children are visited first to last. Only one iterator is kept
+    /// for each node on the current path, so the extra memory used is proportional
+    /// to the depth of the object, however wide it is.
+    ///
+    /// [DropQueue](crate::DropQueue) can't hold iterators, so it treats this
+    /// like [Traversal::DepthFirst].
+    Lazy,
+    /// Visit the nodes depth-first, linking the containers waiting to be dropped
+    /// together through the nodes inside them, using
+    /// [Recursive::spare_link](crate::Recursive::spare_link) and
+    /// [IntoOptionInner::inner_spare_link](crate::IntoOptionInner::inner_spare_link), instead of
+    /// collecting them in a worklist. Children are visited last to first.
+    /// This uses constant extra memory and doesn't allocate, so it can be used when
+    /// memory has run out.
+    ///
+    /// Nodes in uniquely owned containers that don't provide a spare link are kept in
+    /// an allocated list instead, as are containers deferred by nested drops
+    /// (see [Recursive::REENTRANT](crate::Recursive::REENTRANT)).
+    /// [DropQueue](crate::DropQueue) treats this like [Traversal::DepthFirst].
+    Threaded,
+}
+
+impl Traversal {
+    /// Returns `true` if the worklist is only ever used as a stack.
+    #[inline]
+    pub(crate) fn is_depth_first(self) -> bool {
+        matches!(
+            self,
+            Traversal::DepthFirst | Traversal::Lazy | Traversal::Threaded
+        )
+    }
+
+    /// Takes the next container to visit from `list`, where new containers are
+    /// pushed onto the back.
+    #[inline]
+    pub(crate) fn pop<K>(self, list: &mut VecDeque<K>) -> Option<K> {
+        match self {
+            Traversal::DepthFirst | Traversal::Lazy | Traversal::Threaded => list.pop_back(),
+            Traversal::BreadthFirst => list.pop_front(),
+            Traversal::Hybrid { max_width } if list.len() > max_width => list.pop_back(),
+            Traversal::Hybrid { .. } => list.pop_front(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+    use core::cell::Cell;
+
+    use crate::{
+        tests::{on_small_stack, Logged, Order, STACK_SIZE},
+        FlatDrop, Recursive, Traversal,
+    };
+
+    /// Takes trees apart with [Traversal::Hybrid].
+    struct Hybrid<const MAX_WIDTH: usize>;
+
+    impl<const MAX_WIDTH: usize> Order for Hybrid<MAX_WIDTH> {
+        const TRAVERSAL: Traversal = Traversal::Hybrid {
+            max_width: MAX_WIDTH,
+        };
+    }
+
+    fn drop_order<const MAX_WIDTH: usize>() -> Vec<u32> {
+        Logged::<Hybrid<MAX_WIDTH>>::drop_order(|node| {
+            node(
+                0,
+                vec![
+                    node(1, vec![node(3, vec![]), node(4, vec![])]),
+                    node(2, vec![node(5, vec![]), node(6, vec![])]),
+                ],
+            )
+        })
+    }
+
+    #[test]
+    fn test_traversal_order() {
+        // With no room, the worklist is always used as a stack.
+        assert_eq!(drop_order::<0>(), [0, 2, 6, 5, 1, 4, 3]);
+        // With unlimited room, it is always used as a queue.
+        assert_eq!(drop_order::<{ usize::MAX }>(), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(drop_order::<2>(), [0, 1, 4, 2, 6, 3, 5]);
+    }
+
+    /// Counts the leaves that currently exist, and the most that have existed at once.
+    #[derive(Default)]
+    struct Census {
+        live: Cell<usize>,
+        peak: Cell<usize>,
+    }
+
+    struct Member(Rc<Census>);
+
+    impl Member {
+        fn new(census: &Rc<Census>) -> Self {
+            census.live.set(census.live.get() + 1);
+            census.peak.set(census.peak.get().max(census.live.get()));
+            Member(Rc::clone(census))
+        }
+    }
+
+    impl Drop for Member {
+        fn drop(&mut self) {
+            self.0.live.set(self.0.live.get() - 1);
+        }
+    }
+
+    /// A tree whose wide nodes only create their leaves as they're taken apart.
+    enum Wide {
+        Leaf(#[allow(dead_code)] Member),
+        Node(Rc<Census>, usize),
+    }
+
+    impl Recursive for Wide {
+        type Container = Box<Wide>;
+
+        const TRAVERSAL: Traversal = Traversal::Lazy;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            let node = match self {
+                Wide::Leaf(_) => None,
+                Wide::Node(census, width) => Some((census, width)),
+            };
+            node.into_iter().flat_map(|(census, width)| {
+                (0..width).map(move |_| Box::new(Wide::Leaf(Member::new(&census))))
+            })
+        }
+    }
+
+    #[test]
+    fn test_lazy_holds_one_child_at_a_time() {
+        let census = Rc::new(Census::default());
+        let tree = FlatDrop::new_boxed(Wide::Node(Rc::clone(&census), 100_000));
+        assert_eq!(tree.drop_counted().freed, 100_001);
+        assert_eq!(census.live.get(), 0);
+        assert_eq!(census.peak.get(), 1);
+    }
+
+    /// A tree that links pending containers through its nodes.
+    struct Threaded {
+        children: Vec<FlatDrop<Rc<Threaded>>>,
+        link: Option<Rc<Threaded>>,
+    }
+
+    impl Recursive for Threaded {
+        type Container = Rc<Threaded>;
+
+        const TRAVERSAL: Traversal = Traversal::Threaded;
+
+        fn destruct(self) -> impl Iterator<Item = Self::Container> {
+            let children = self.children.into_iter().map(FlatDrop::into_inner);
+            children.chain(self.link)
+        }
+
+        fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+            Some(&mut self.link)
+        }
+    }
+
+    fn threaded(children: Vec<FlatDrop<Rc<Threaded>>>) -> FlatDrop<Rc<Threaded>> {
+        FlatDrop::new_rc(Threaded {
+            children,
+            link: None,
+        })
+    }
+
+    #[test]
+    fn test_threaded_large_tree() {
+        on_small_stack(|| {
+            let shared = Rc::new(Threaded {
+                children: vec![threaded(vec![])],
+                link: None,
+            });
+            let tree = (0..STACK_SIZE * 100).fold(threaded(vec![]), |tree, _| {
+                let shared = FlatDrop::new(Rc::clone(&shared));
+                threaded(vec![tree, threaded(vec![]), shared])
+            });
+            let stats = tree.drop_counted();
+            assert_eq!(stats.freed, STACK_SIZE * 100 * 2 + 1);
+            assert_eq!(stats.shared, STACK_SIZE * 100);
+            // The last reference to the shared node is ours.
+            assert_eq!(Rc::strong_count(&shared), 1);
+            assert!(shared.link.is_none());
+        });
+    }
+}
@@ -0,0 +1,270 @@
+//! The list of containers waiting to be dropped.
+//!
+//! Dropping a [FlatDrop](crate::FlatDrop) is extremely common, so we try hard to avoid
+//! allocating a fresh list each time. Each thread keeps hold of one spare allocation,
+//! left behind by the last worklist that it dropped, which the next worklist reuses.
+//! Since the container types are usually all pointer-sized, one spare is plenty.
+//! Without the `std` feature there are no thread-locals, so every worklist allocates afresh.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::mem;
+#[cfg(feature = "std")]
+use core::{alloc::Layout, cell::Cell, mem::ManuallyDrop, ptr::NonNull};
+
+use crate::Traversal;
+
+/// The largest allocation, in bytes, that we keep around for reuse.
+/// Larger allocations are freed so that one huge drop doesn't pin memory forever.
+#[cfg(feature = "std")]
+const MAX_SPARE_BYTES: usize = 64 * 1024;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// A spare allocation left behind by a previous worklist on this thread.
+    static SPARE: Cell<Option<Allocation>> = const { Cell::new(None) };
+}
+
+/// An allocation that used to belong to an empty `Vec`.
+#[cfg(feature = "std")]
+struct Allocation {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+#[cfg(feature = "std")]
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        // Safety: the allocation was made by the global allocator with this layout,
+        // and we own it exclusively.
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// A list of containers to drop.
+/// Each operation is given the [Traversal] of the type being dropped,
+/// which decides which list is used and which end containers are taken from.
+/// Its storage is only allocated once the first container is pushed,
+/// and is returned to this thread's spare slot when the worklist is dropped.
+pub(crate) struct Worklist<K> {
+    /// The containers, if the traversal is depth-first.
+    /// A `Vec` is a little faster than a `VecDeque` when used as a stack.
+    stack: Vec<K>,
+    /// The containers, for any other traversal.
+    queue: VecDeque<K>,
+}
+
+impl<K> Worklist<K> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Pushes a container, or hands it back if the worklist couldn't grow.
+    #[inline]
+    pub(crate) fn push(&mut self, container: K, traversal: Traversal) -> Result<(), K> {
+        if traversal.is_depth_first() {
+            if self.stack.capacity() == 0 {
+                self.stack = take_spare();
+            }
+            if self.stack.try_reserve(1).is_err() {
+                return Err(container);
+            }
+            self.stack.push(container);
+        } else {
+            if self.queue.capacity() == 0 {
+                self.queue = take_spare().into();
+            }
+            if self.queue.try_reserve(1).is_err() {
+                return Err(container);
+            }
+            self.queue.push_back(container);
+        }
+        Ok(())
+    }
+
+    /// Pushes every container from `iter`. If the worklist couldn't grow, hands back
+    /// the container that didn't fit, leaving the rest in `iter`.
+    #[inline]
+    pub(crate) fn extend<I>(&mut self, iter: &mut I, traversal: Traversal) -> Result<(), K>
+    where
+        I: Iterator<Item = K>,
+    {
+        // In the common case, there's already room for every container.
+        // `for_each` lets the iterator drive itself, which is much faster than
+        // `extend(iter.by_ref())` for nested iterators such as `Flatten`.
+        let upper = iter.size_hint().1;
+        if traversal.is_depth_first() {
+            if self.stack.capacity() == 0 && upper != Some(0) {
+                self.stack = take_spare();
+            }
+            if upper.is_some_and(|upper| self.stack.capacity() - self.stack.len() >= upper) {
+                iter.for_each(|container| self.stack.push(container));
+                return Ok(());
+            }
+        } else {
+            if self.queue.capacity() == 0 && upper != Some(0) {
+                self.queue = take_spare().into();
+            }
+            if upper.is_some_and(|upper| self.queue.capacity() - self.queue.len() >= upper) {
+                iter.for_each(|container| self.queue.push_back(container));
+                return Ok(());
+            }
+        }
+        self.extend_slow(iter, traversal)
+    }
+
+    /// Pushes every container from `iter`, growing the worklist if possible.
+    #[cold]
+    #[inline(never)]
+    fn extend_slow<I>(&mut self, iter: &mut I, traversal: Traversal) -> Result<(), K>
+    where
+        I: Iterator<Item = K>,
+    {
+        // If we can make room for as many containers as there could be,
+        // extending won't need to allocate.
+        let (lower, upper) = iter.size_hint();
+        let room = upper.unwrap_or(lower);
+        if upper.is_some() {
+            if traversal.is_depth_first() {
+                if self.stack.try_reserve(room).is_ok() {
+                    self.stack.extend(iter.by_ref());
+                    return Ok(());
+                }
+            } else if self.queue.try_reserve(room).is_ok() {
+                self.queue.extend(iter.by_ref());
+                return Ok(());
+            }
+        }
+        // Otherwise, push them one at a time.
+        iter.try_for_each(|container| self.push(container, traversal))
+    }
+
+    #[inline]
+    pub(crate) fn pop(&mut self, traversal: Traversal) -> Option<K> {
+        if traversal.is_depth_first() {
+            self.stack.pop()
+        } else {
+            traversal.pop(&mut self.queue)
+        }
+    }
+}
+
+impl<K> Drop for Worklist<K> {
+    fn drop(&mut self) {
+        // The lists are only non-empty here if we're unwinding from a panic.
+        if self.stack.capacity() != 0 {
+            self.stack.clear();
+            give_back(mem::take(&mut self.stack));
+        }
+        if self.queue.capacity() != 0 {
+            self.queue.clear();
+            give_back(mem::take(&mut self.queue).into());
+        }
+    }
+}
+
+/// Takes this thread's spare allocation if it can hold values of type `K`,
+/// and otherwise returns a new (unallocated) `Vec`.
+#[cfg(feature = "std")]
+#[cold]
+fn take_spare<K>() -> Vec<K> {
+    let size = mem::size_of::<K>();
+    if size == 0 {
+        return Vec::new();
+    }
+
+    SPARE
+        .try_with(|spare| {
+            let allocation = spare.take()?;
+            if allocation.layout.align() != mem::align_of::<K>()
+                || allocation.layout.size() % size != 0
+            {
+                spare.set(Some(allocation));
+                return None;
+            }
+
+            let allocation = ManuallyDrop::new(allocation);
+            // Safety: the allocation was made by the global allocator with the same
+            // alignment as `K`, and its size is exactly `capacity * size_of::<K>()`.
+            // Ownership passes to the new `Vec`.
+            Some(unsafe {
+                Vec::from_raw_parts(
+                    allocation.ptr.as_ptr().cast::<K>(),
+                    0,
+                    allocation.layout.size() / size,
+                )
+            })
+        })
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "std"))]
+fn take_spare<K>() -> Vec<K> {
+    Vec::new()
+}
+
+/// Stores the allocation of an empty `Vec` in this thread's spare slot,
+/// if it's worth keeping.
+#[cfg(feature = "std")]
+fn give_back<K>(list: Vec<K>) {
+    debug_assert!(list.is_empty());
+    if mem::size_of::<K>() == 0 || list.capacity() == 0 {
+        return;
+    }
+    let layout = match Layout::array::<K>(list.capacity()) {
+        Ok(layout) if layout.size() <= MAX_SPARE_BYTES => layout,
+        _ => return,
+    };
+
+    let mut list = ManuallyDrop::new(list);
+    let allocation = Allocation {
+        // Safety: a `Vec` with non-zero capacity has a non-null pointer.
+        ptr: unsafe { NonNull::new_unchecked(list.as_mut_ptr().cast::<u8>()) },
+        layout,
+    };
+
+    // If the thread is shutting down, the allocation is freed instead.
+    let _ = SPARE.try_with(move |spare| {
+        // Keep whichever allocation is larger.
+        match spare.take() {
+            Some(old) if old.layout.size() > allocation.layout.size() => spare.set(Some(old)),
+            _ => spare.set(Some(allocation)),
+        }
+    });
+}
+
+#[cfg(not(feature = "std"))]
+fn give_back<K>(list: Vec<K>) {
+    drop(list);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Worklist;
+    use crate::Traversal;
+
+    #[test]
+    fn test_reuse_allocation() {
+        let mut worklist = Worklist::new();
+        assert!(worklist
+            .extend(&mut [1u64, 2, 3].into_iter(), Traversal::DepthFirst)
+            .is_ok());
+        let ptr = worklist.stack.as_ptr();
+        drop(worklist);
+
+        // A list of a different type with the same alignment can reuse the allocation,
+        // even if it's used as a queue.
+        let mut worklist = Worklist::new();
+        assert!(worklist
+            .extend(&mut [4i64, 5].into_iter(), Traversal::BreadthFirst)
+            .is_ok());
+        assert_eq!(worklist.queue.as_slices().0.as_ptr().cast::<u64>(), ptr);
+        assert_eq!(worklist.pop(Traversal::BreadthFirst), Some(4));
+        assert_eq!(worklist.pop(Traversal::BreadthFirst), Some(5));
+        assert_eq!(worklist.pop(Traversal::BreadthFirst), None);
+    }
+}
@@ -0,0 +1,33 @@
+//! Checks that the crate builds without the `std` feature.
+//! With `std` disabled the crate is `#![no_std]`, so any accidental use of `std`
+//! is a compile error.
+
+use std::process::Command;
+
+fn check(features: &str) {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    // Use a separate target directory so that we don't wait on the lock held by
+    // the `cargo test` that is running us.
+    let target = concat!(env!("CARGO_MANIFEST_DIR"), "/../target/no-std");
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "check",
+            "--lib",
+            "--no-default-features",
+            "--features",
+            features,
+        ])
+        .args(["--manifest-path", manifest, "--target-dir", target])
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "`cargo check` failed with features {features:?}"
+    );
+}
+
+#[test]
+fn test_no_std() {
+    check("");
+    check("derive,serde");
+}
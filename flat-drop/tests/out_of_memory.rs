@@ -0,0 +1,262 @@
+//! Dropping while every allocation fails.
+//!
+//! This installs a global allocator that can be made to fail, so it lives in its
+//! own test binary rather than affecting the crate's other tests.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    ptr,
+};
+
+use flat_drop::{family, FlatDrop, Recursive, Traversal};
+
+std::thread_local! {
+    /// Whether allocations on this thread should fail.
+    static OUT_OF_MEMORY: Cell<bool> = const { Cell::new(false) };
+    /// How many allocations have failed on this thread.
+    static FAILED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The system allocator, except that it fails on threads that are out of memory.
+struct FallibleAlloc;
+
+unsafe impl GlobalAlloc for FallibleAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if OUT_OF_MEMORY.try_with(Cell::get).unwrap_or(false) {
+            FAILED.set(FAILED.get() + 1);
+            return ptr::null_mut();
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: FallibleAlloc = FallibleAlloc;
+
+/// Runs `f` with every allocation on this thread failing.
+fn out_of_memory<R>(f: impl FnOnce() -> R) -> R {
+    OUT_OF_MEMORY.set(true);
+    let result = f();
+    OUT_OF_MEMORY.set(false);
+    result
+}
+
+/// Peano natural numbers, whose `destruct` is derived.
+#[cfg(feature = "derive")]
+#[derive(Recursive)]
+enum Natural {
+    Zero,
+    Succ(FlatDrop<Box<Natural>>),
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derived_out_of_memory() {
+    // A derived `destruct` knows it yields at most one child here, so taking
+    // the number apart never needs the worklist.
+    const STACK_SIZE: usize = 4 * 1024;
+
+    fn task() {
+        let nat = (0..STACK_SIZE * 100).fold(Natural::Zero, |nat, _| {
+            Natural::Succ(FlatDrop::new(Box::new(nat)))
+        });
+        let failed = FAILED.get();
+        out_of_memory(|| drop(std::hint::black_box(nat)));
+        assert_eq!(FAILED.get(), failed);
+    }
+
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(task)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// A tree whose nodes may have a spare link.
+struct Linkable {
+    children: Vec<FlatDrop<Box<Linkable>>>,
+    link: Option<Option<Box<Linkable>>>,
+}
+
+impl Recursive for Linkable {
+    type Container = Box<Linkable>;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        let children = self.children.into_iter().map(FlatDrop::into_inner);
+        children.chain(self.link.flatten())
+    }
+
+    fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+        self.link.as_mut()
+    }
+}
+
+fn linkable(children: Vec<FlatDrop<Box<Linkable>>>, link: bool) -> FlatDrop<Box<Linkable>> {
+    FlatDrop::new_boxed(Linkable {
+        children,
+        link: link.then_some(None),
+    })
+}
+
+#[test]
+fn test_drop_out_of_memory() {
+    // Create a new thread with a 4kb stack and drop a tree far deeper than 4 * 1024,
+    // without being able to allocate a worklist.
+    const STACK_SIZE: usize = 4 * 1024;
+
+    fn task() {
+        let leaf = || linkable(vec![], true);
+        let tree = (0..STACK_SIZE * 100)
+            .fold(leaf(), |tree, _| linkable(vec![tree, leaf(), leaf()], true));
+        let stats = out_of_memory(|| tree.drop_counted());
+        assert_eq!(stats.freed, STACK_SIZE * 100 * 3 + 1);
+
+        // Without spare links, nodes are dropped directly.
+        let leaves = (0..1000).map(|_| linkable(vec![], false)).collect();
+        let tree = linkable(leaves, false);
+        let stats = out_of_memory(|| tree.drop_counted());
+        assert_eq!(stats.freed, 1001);
+    }
+
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(task)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// A tree that links pending containers through its nodes.
+struct Threaded {
+    children: Vec<FlatDrop<Box<Threaded>>>,
+    link: Option<Box<Threaded>>,
+}
+
+impl Recursive for Threaded {
+    type Container = Box<Threaded>;
+
+    const TRAVERSAL: Traversal = Traversal::Threaded;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        let children = self.children.into_iter().map(FlatDrop::into_inner);
+        children.chain(self.link)
+    }
+
+    fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+        Some(&mut self.link)
+    }
+}
+
+#[test]
+fn test_threaded_out_of_memory() {
+    // Every node has a spare link, so a threaded drop never even tries to allocate.
+    const STACK_SIZE: usize = 4 * 1024;
+
+    fn task() {
+        let leaf = || {
+            FlatDrop::new_boxed(Threaded {
+                children: vec![],
+                link: None,
+            })
+        };
+        let tree = (0..STACK_SIZE * 100).fold(leaf(), |tree, _| {
+            FlatDrop::new_boxed(Threaded {
+                children: vec![tree, leaf(), leaf()],
+                link: None,
+            })
+        });
+        let failed = FAILED.get();
+        let stats = out_of_memory(|| tree.drop_counted());
+        assert_eq!(stats.freed, STACK_SIZE * 100 * 3 + 1);
+        assert_eq!(FAILED.get(), failed);
+    }
+
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(task)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// A block of statements, which has a spare link.
+struct Block {
+    stmts: Vec<FlatDrop<Box<Stmt>>>,
+    link: Option<Node>,
+}
+
+/// A statement holding a block, which has a spare link.
+struct Stmt {
+    block: FlatDrop<Box<Block>>,
+    link: Option<Node>,
+}
+
+family! {
+    enum Node {
+        Block(Box<Block>),
+        Stmt(Box<Stmt>),
+    }
+
+    enum NodeInner;
+}
+
+impl Recursive for Block {
+    type Container = Node;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        let stmts = self.stmts.into_iter().map(|stmt| stmt.into_inner().into());
+        stmts.chain(self.link)
+    }
+
+    fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+        Some(&mut self.link)
+    }
+}
+
+impl Recursive for Stmt {
+    type Container = Node;
+
+    fn destruct(self) -> impl Iterator<Item = Self::Container> {
+        std::iter::once(self.block.into_inner().into()).chain(self.link)
+    }
+
+    fn spare_link(&mut self) -> Option<&mut Option<Self::Container>> {
+        Some(&mut self.link)
+    }
+}
+
+#[test]
+fn test_drop_family_out_of_memory() {
+    // The family's containers lend out their members' spare links, so this doesn't
+    // need to drop any nodes directly.
+    const STACK_SIZE: usize = 4 * 1024;
+
+    fn task() {
+        let block = |stmts| Block { stmts, link: None };
+        let stmt = |block| {
+            FlatDrop::new_boxed(Stmt {
+                block: FlatDrop::new_boxed(block),
+                link: None,
+            })
+        };
+        let tree = (0..STACK_SIZE * 100).fold(block(vec![]), |tree, _| {
+            block(vec![stmt(tree), stmt(block(vec![])), stmt(block(vec![]))])
+        });
+        let tree = FlatDrop::new(Node::from(Box::new(tree)));
+        let stats = out_of_memory(|| tree.drop_counted());
+        assert_eq!(stats.freed, STACK_SIZE * 100 * 6 + 1);
+    }
+
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(task)
+        .unwrap()
+        .join()
+        .unwrap();
+}